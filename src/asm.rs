@@ -0,0 +1,412 @@
+use crate::instruction::{InstructionSpec, OperandKind, INSTRUCTIONS};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// deepest chain of INCLUDE allowed, stops include loops
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An assembly error and the line it comes from
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    // 1 based, 0 when the error isn't tied to a line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{} : {}", self.file, self.message)
+        } else {
+            write!(f, "{} line {} : {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// A source line once the includes are expanded, comments and labels removed
+struct Line {
+    file: String,
+    number: usize,
+    labels: Vec<String>,
+    // upper case mnemonic or directive, empty for label-only lines
+    operation: String,
+    operands: Vec<String>,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.number, message }
+    }
+}
+
+/// What a line turns into, decided on the first pass so the labels get their addresses
+enum Statement {
+    Nothing,
+    Instruction(&'static InstructionSpec),
+    Bytes(usize),
+    Words(usize),
+    Origin(usize),
+    Constant,
+}
+
+/// Removes the `;` comment, ignoring the semicolons inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+/// Splits the operands on the commas that are outside of strings
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                current.push(c);
+            },
+            ',' if !in_string => operands.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+
+    operands
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Index of a register written `V0` to `VF`
+fn register(text: &str) -> Option<u8> {
+    let text = text.to_ascii_uppercase();
+    let index = text.strip_prefix('V')?;
+    if index.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(index, 16).ok()
+}
+
+// keywords that can't be used as the name of a label or constant
+fn is_keyword(text: &str) -> bool {
+    let upper = text.to_ascii_uppercase();
+    register(text).is_some() || matches!(upper.as_str(), "I" | "DT" | "ST" | "K" | "F" | "HF" | "B" | "R" | "LONG")
+}
+
+/// Parses `0x1F`, `$1F`, `0b0101`, `%0101` or decimal
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix('%')) {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+
+    i64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+/// Symbols known so far : labels and constants
+struct Symbols {
+    values: HashMap<String, i64>,
+}
+
+impl Symbols {
+    /// Evaluates terms added or subtracted, like `sprites + 5` or `0x300`
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let expression = expression.trim();
+        if expression.is_empty() {
+            return Err(String::from("missing value"));
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut term = String::new();
+        let mut terms = Vec::new();
+        for c in expression.chars() {
+            match c {
+                '+' | '-' if !term.trim().is_empty() => {
+                    terms.push((sign, std::mem::take(&mut term)));
+                    sign = if c == '-' { -1 } else { 1 };
+                },
+                '-' => sign = -sign,
+                '+' => (),
+                _ => term.push(c),
+            }
+        }
+        terms.push((sign, term));
+
+        for (sign, term) in terms {
+            let term = term.trim();
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None if is_identifier(term) => *self.values.get(term).ok_or_else(|| format!("unknown label or constant '{}'", term))?,
+                None => return Err(format!("invalid value '{}'", term)),
+            };
+            total = value.checked_mul(sign).and_then(|value| total.checked_add(value))
+                .ok_or_else(|| format!("'{}' overflows", expression))?;
+        }
+
+        Ok(total)
+    }
+}
+
+// `LONG addr`, the 16 bits address of F000 NNNN
+fn is_long(operand: &str) -> bool {
+    operand.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("LONG "))
+}
+
+/// Whether `operand` can be written as an operand of this kind, only the shape is checked
+fn operand_matches(kind: OperandKind, operand: &str) -> bool {
+    match kind {
+        OperandKind::Vx | OperandKind::Vy => register(operand).is_some(),
+        OperandKind::Fixed(keyword) => operand.eq_ignore_ascii_case(keyword),
+        OperandKind::Long => is_long(operand),
+        _ => !is_keyword(operand) && !is_long(operand) && !operand.starts_with('['),
+    }
+}
+
+/// The table entry for a mnemonic and its operands
+fn find_spec(line: &Line) -> Result<&'static InstructionSpec, AsmError> {
+    let mut candidates = INSTRUCTIONS.iter().filter(|spec| spec.mnemonic == line.operation).peekable();
+    if candidates.peek().is_none() {
+        return Err(line.error(format!("unknown instruction '{}'", line.operation)));
+    }
+
+    candidates
+        .find(|spec| spec.operands.len() == line.operands.len()
+            && spec.operands.iter().zip(line.operands.iter()).all(|(kind, operand)| operand_matches(*kind, operand)))
+        .ok_or_else(|| line.error(format!("invalid operands for {} : {}", line.operation, line.operands.join(", "))))
+}
+
+fn encode(spec: &InstructionSpec, line: &Line, symbols: &Symbols) -> Result<Vec<u8>, AsmError> {
+    let mut opcode = spec.pattern;
+    let mut long = None;
+
+    for (kind, operand) in spec.operands.iter().zip(line.operands.iter()) {
+        let value = |max: i64| -> Result<u16, AsmError> {
+            let expression = if *kind == OperandKind::Long { &operand[5..] } else { operand.as_str() };
+            let value = symbols.evaluate(expression).map_err(|message| line.error(message))?;
+            // bytes can be written as negative numbers
+            let value = if *kind == OperandKind::Byte && (-128..0).contains(&value) { value + 256 } else { value };
+            if value < 0 || value > max {
+                return Err(line.error(format!("{} doesn't fit in {} ({} max)", operand, line.operation, max)));
+            }
+            Ok(value as u16)
+        };
+
+        match kind {
+            OperandKind::Vx => opcode |= (register(operand).unwrap_or(0) as u16) << 8,
+            OperandKind::Vy => opcode |= (register(operand).unwrap_or(0) as u16) << 4,
+            OperandKind::Byte => opcode |= value(0xFF)?,
+            OperandKind::Addr => opcode |= value(0xFFF)?,
+            OperandKind::Nibble => opcode |= value(0xF)?,
+            OperandKind::Planes => opcode |= value(0x3)? << 8,
+            OperandKind::Long => long = Some(value(0xFFFF)?),
+            OperandKind::Fixed(_) => (),
+        };
+    }
+
+    let mut bytes = opcode.to_be_bytes().to_vec();
+    if let Some(long) = long {
+        bytes.extend_from_slice(&long.to_be_bytes());
+    }
+
+    Ok(bytes)
+}
+
+/// Values of DB/DW operands, DB also takes strings
+fn data_values(line: &Line, symbols: &Symbols, max: i64) -> Result<Vec<i64>, AsmError> {
+    let mut values = Vec::new();
+    for operand in line.operands.iter() {
+        if max == 0xFF && operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+            values.extend(operand[1..operand.len() - 1].bytes().map(|byte| byte as i64));
+            continue;
+        }
+
+        let value = symbols.evaluate(operand).map_err(|message| line.error(message))?;
+        let value = if (-(max + 1) / 2..0).contains(&value) { value + max + 1 } else { value };
+        if value < 0 || value > max {
+            return Err(line.error(format!("{} doesn't fit in {}", operand, line.operation)));
+        }
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+fn data_length(line: &Line) -> usize {
+    line.operands.iter().map(|operand| {
+        if line.operation == "DB" && operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+            operand.len() - 2
+        } else {
+            1
+        }
+    }).sum()
+}
+
+/// Reads the source files, a closure so the tests don't need files
+pub type SourceReader<'a> = dyn Fn(&Path) -> Result<String, String> + 'a;
+
+fn read_lines(path: &Path, reader: &SourceReader, depth: usize, lines: &mut Vec<Line>) -> Result<(), AsmError> {
+    let file = path.display().to_string();
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(AsmError { file, line: 0, message: String::from("too many nested includes") });
+    }
+    let text = reader(path).map_err(|message| AsmError { file: file.clone(), line: 0, message })?;
+
+    for (index, raw) in text.lines().enumerate() {
+        let mut rest = strip_comment(raw).trim();
+        let error = |message: String| AsmError { file: file.clone(), line: index + 1, message };
+
+        // any number of `label:` before the instruction
+        let mut labels = Vec::new();
+        while let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if !is_identifier(name) || rest[..colon].contains('"') {
+                break;
+            }
+            if is_keyword(name) {
+                return Err(error(format!("'{}' can't be used as a label", name)));
+            }
+            labels.push(name.to_string());
+            rest = rest[colon + 1..].trim();
+        }
+
+        let (operation, operands) = match rest.find(char::is_whitespace) {
+            Some(space) => (&rest[..space], rest[space..].trim()),
+            None => (rest, ""),
+        };
+
+        // `NAME = value` defines a constant
+        if let Some(value) = rest.split_once('=').filter(|(name, _)| is_identifier(name.trim())).map(|(_, value)| value) {
+            let name = rest.split('=').next().unwrap_or("").trim();
+            if is_keyword(name) {
+                return Err(error(format!("'{}' can't be used as a constant", name)));
+            }
+            lines.push(Line { file: file.clone(), number: index + 1, labels, operation: String::from("="), operands: vec![name.to_string(), value.trim().to_string()] });
+            continue;
+        }
+        if operation == "=" {
+            return Err(error(String::from("expected `NAME = value`")));
+        }
+
+        let operation = operation.to_ascii_uppercase();
+        if operation == "INCLUDE" {
+            let name = operands.trim_matches('"');
+            let included = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+            if !labels.is_empty() {
+                lines.push(Line { file: file.clone(), number: index + 1, labels, operation: String::new(), operands: Vec::new() });
+            }
+            read_lines(&included, reader, depth + 1, lines)?;
+            continue;
+        }
+
+        lines.push(Line { file: file.clone(), number: index + 1, labels, operation, operands: split_operands(operands) });
+    }
+
+    Ok(())
+}
+
+/// Assembles the source at `path` into a ROM meant to be loaded at `origin`.
+/// `reader` gives the text of the source and of its includes
+pub fn assemble_with(path: &Path, origin: usize, reader: &SourceReader) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    read_lines(path, reader, 0, &mut lines)?;
+
+    // first pass : addresses of the labels, values of the constants
+    let mut symbols = Symbols { values: HashMap::new() };
+    let mut statements = Vec::new();
+    let mut address = origin;
+    for line in lines.iter() {
+        for label in line.labels.iter() {
+            if symbols.values.insert(label.clone(), address as i64).is_some() {
+                return Err(line.error(format!("'{}' is defined twice", label)));
+            }
+        }
+
+        let statement = match line.operation.as_str() {
+            "" => Statement::Nothing,
+            "=" => {
+                let value = symbols.evaluate(&line.operands[1]).map_err(|message| line.error(message))?;
+                if symbols.values.insert(line.operands[0].clone(), value).is_some() {
+                    return Err(line.error(format!("'{}' is defined twice", line.operands[0])));
+                }
+                Statement::Constant
+            },
+            "DB" => Statement::Bytes(data_length(line)),
+            "DW" => Statement::Words(line.operands.len()),
+            "ORG" => {
+                let target = symbols.evaluate(line.operands.first().map(|operand| operand.as_str()).unwrap_or(""))
+                    .map_err(|message| line.error(message))?;
+                if target < address as i64 {
+                    return Err(line.error(format!("ORG 0x{:X} goes back before the current address 0x{:X}", target, address)));
+                }
+                Statement::Origin(target as usize)
+            },
+            _ => Statement::Instruction(find_spec(line)?),
+        };
+
+        address = match &statement {
+            Statement::Instruction(spec) => address + if spec.operands.contains(&OperandKind::Long) { 4 } else { 2 },
+            Statement::Bytes(count) => address + count,
+            Statement::Words(count) => address + 2 * count,
+            Statement::Origin(target) => *target,
+            Statement::Nothing | Statement::Constant => address,
+        };
+        statements.push(statement);
+    }
+
+    // second pass : every label is known
+    let mut rom = Vec::new();
+    for (line, statement) in lines.iter().zip(statements.iter()) {
+        match statement {
+            Statement::Instruction(spec) => rom.extend(encode(spec, line, &symbols)?),
+            Statement::Bytes(_) => rom.extend(data_values(line, &symbols, 0xFF)?.iter().map(|value| *value as u8)),
+            Statement::Words(_) => {
+                for value in data_values(line, &symbols, 0xFFFF)? {
+                    rom.extend_from_slice(&(value as u16).to_be_bytes());
+                }
+            },
+            Statement::Origin(target) => rom.resize(target - origin, 0),
+            Statement::Nothing | Statement::Constant => (),
+        };
+    }
+
+    Ok(rom)
+}
+
+/// Assembles a source file, the includes are relative to the file including them
+pub fn assemble_file<P: AsRef<Path>>(path: P, origin: usize) -> Result<Vec<u8>, AsmError> {
+    assemble_with(path.as_ref(), origin, &|path: &Path| fs::read_to_string(path).map_err(|err| err.to_string()))
+}
+
+/// Default name of the assembled ROM : the source with the .ch8 extension
+pub fn output_path(source: &Path) -> PathBuf {
+    source.with_extension("ch8")
+}
+
+#[cfg(test)]
+#[path = "asm_test.rs"]
+mod asm_test;
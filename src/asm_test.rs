@@ -0,0 +1,75 @@
+use super::*;
+use crate::disasm;
+
+use std::collections::HashMap;
+
+fn assemble_text(files: &[(&str, &str)]) -> Result<Vec<u8>, AsmError> {
+    let files: HashMap<PathBuf, String> = files.iter().map(|(name, text)| (PathBuf::from(name), text.to_string())).collect();
+    assemble_with(Path::new("main.s"), 0x200, &|path: &Path| files.get(path).cloned().ok_or_else(|| String::from("not found")))
+}
+
+#[test]
+fn assemble_test() {
+    let source = "\
+; draws the digit in v0 forever
+SPEED = 3
+start:  LD V0, SPEED + 1
+        LD I, sprite         ; forward references work too
+        call draw
+loop:   jp loop
+draw:   DRW V0, V1, 5
+        SHR v2, v3
+        LD I, LONG sprite
+        RET
+sprite: DB 0xF0, %10010000, 144, \"AB\"
+        DW 0x1234, -1
+";
+    let rom = assemble_text(&[("main.s", source)]).unwrap();
+    assert_eq!(vec![
+        0x60, 0x04, 0xA2, 0x12, 0x22, 0x08, 0x12, 0x06,
+        0xD0, 0x15, 0x82, 0x36, 0xF0, 0x00, 0x02, 0x12, 0x00, 0xEE,
+        0xF0, 0x90, 0x90, 0x41, 0x42, 0x12, 0x34, 0xFF, 0xFF,
+    ], rom);
+}
+
+#[test]
+fn include_and_org_test() {
+    let main = "JP start\nINCLUDE \"sprites.s\"\nORG 0x210\nstart: LD I, heart\nJP start";
+    let sprites = "heart: DB 0x6C, 0xFE, 0x7C, 0x38, 0x10";
+    let rom = assemble_text(&[("main.s", main), ("sprites.s", sprites)]).unwrap();
+
+    assert_eq!(0x14, rom.len());
+    assert_eq!([0x12, 0x10, 0x6C], rom[..3]);
+    assert_eq!([0xA2, 0x02, 0x12, 0x10], rom[0x10..0x14]);
+}
+
+#[test]
+fn errors_test() {
+    let error = |source: &str| assemble_text(&[("main.s", source)]).unwrap_err().to_string();
+
+    assert_eq!("main.s line 2 : unknown instruction 'MOV'", error("CLS\nMOV V0, V1"));
+    assert_eq!("main.s line 1 : invalid operands for LD : DT, 5", error("LD DT, 5"));
+    assert_eq!("main.s line 3 : unknown label or constant 'nowhere'", error("CLS\n\nJP nowhere"));
+    assert_eq!("main.s line 1 : 0x100 doesn't fit in LD (255 max)", error("LD V0, 0x100"));
+    assert_eq!("main.s line 2 : 'a' is defined twice", error("a: CLS\na: CLS"));
+    assert_eq!("main.s line 1 : 'VF' can't be used as a label", error("VF: CLS"));
+    assert_eq!("missing.s : not found", error("INCLUDE \"missing.s\""));
+    assert_eq!("main.s line 1 : expected `NAME = value`", error("= 5"));
+    assert_eq!("main.s line 2 : expected `NAME = value`", error("CLS\n="));
+    assert_eq!("main.s line 1 : expected `NAME = value`", error("x: ="));
+    assert_eq!("main.s line 2 : 'BIG + BIG' overflows", error("BIG = 0x7FFFFFFFFFFFFFFF\nLD V0, BIG + BIG"));
+    assert_eq!("main.s line 1 : '1 - 0x7FFFFFFFFFFFFFFF - 3' overflows", error("LOW = 1 - 0x7FFFFFFFFFFFFFFF - 3"));
+}
+
+#[test]
+fn disassembly_round_trip_test() {
+    let mut games = fs::read_dir(crate::cli::GAMES_DIR).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+    games.sort();
+
+    for game in games {
+        let rom = fs::read(&game).unwrap();
+        let listing = disasm::disassemble(&rom, 0x200).to_string();
+        let assembled = assemble_with(Path::new("listing.s"), 0x200, &|_: &Path| Ok(listing.clone())).unwrap();
+        assert_eq!(rom, assembled, "{} doesn't survive a round trip", game.display());
+    }
+}
@@ -0,0 +1,167 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+// length of the fade in/out applied when the beep starts or stops, avoids clicks
+const RAMP_SECONDS: f32 = 0.005;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// Value of the waveform in [-1, 1] at `phase` in [0, 1)
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Waveform, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform '{}', expected square, triangle, sawtooth or sine", name)),
+        }
+    }
+}
+
+/// Sound of the beep played while the sound timer is non zero
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioConfig {
+    // frequency of the tone in Hz
+    pub frequency: f32,
+    // volume between 0 and 1
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+// XO-CHIP pitch register value giving a 4000Hz sample rate
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// XO-CHIP audio pattern : 128 one-bit samples looped at the rate given by the pitch register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioPattern {
+    pub bits: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// Samples played per second, 4000 * 2 ^ ((pitch - 64) / 48)
+    pub fn rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+
+    /// Value of the pattern in [-1, 1] at `phase` in [0, 1)
+    fn sample(&self, phase: f32) -> f32 {
+        let bit = ((phase * 128.0) as usize).min(127);
+        if (self.bits[bit / 8] >> (7 - bit % 8)) & 0x01 == 1 { 1.0 } else { -1.0 }
+    }
+}
+
+/// Receives the beeper state from the core once per frame
+pub trait AudioSink {
+    fn set_beeping(&mut self, beeping: bool) -> Result<(), String>;
+
+    /// Sets the XO-CHIP pattern played instead of the tone, None goes back to the tone.
+    /// Sinks that can't play patterns keep the tone
+    fn set_pattern(&mut self, _pattern: Option<AudioPattern>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Audio sink that stays silent, for headless runs and --mute
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_beeping(&mut self, _beeping: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Tone generator shared by the audio backends.
+/// Starting and stopping fades the amplitude instead of cutting it, so the beep doesn't click
+pub struct Tone {
+    config: AudioConfig,
+    sample_rate: f32,
+    // position inside the current period, in [0, 1)
+    phase: f32,
+    // current amplitude envelope, moves towards 1 while beeping and towards 0 otherwise
+    gain: f32,
+    beeping: bool,
+    // played instead of the waveform when set
+    pattern: Option<AudioPattern>,
+}
+
+impl Tone {
+    pub fn new(config: AudioConfig, sample_rate: u32) -> Tone {
+        Tone {
+            config,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            gain: 0.0,
+            beeping: false,
+            pattern: None,
+        }
+    }
+
+    pub fn set_beeping(&mut self, beeping: bool) {
+        self.beeping = beeping;
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    /// Fills `out` with the next samples of the tone
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let ramp_step = 1.0 / (RAMP_SECONDS * self.sample_rate).max(1.0);
+        // a pattern period is the whole 128 samples
+        let phase_step = match &self.pattern {
+            Some(pattern) => pattern.rate() / 128.0 / self.sample_rate,
+            None => self.config.frequency / self.sample_rate,
+        };
+        let target = if self.beeping { 1.0 } else { 0.0 };
+
+        for sample in out.iter_mut() {
+            if self.gain < target {
+                self.gain = (self.gain + ramp_step).min(target);
+            } else if self.gain > target {
+                self.gain = (self.gain - ramp_step).max(target);
+            }
+
+            let value = match &self.pattern {
+                Some(pattern) => pattern.sample(self.phase),
+                None => self.config.waveform.sample(self.phase),
+            };
+            *sample = value * self.config.volume * self.gain;
+            self.phase = (self.phase + phase_step) % 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "audio_test.rs"]
+mod audio_test;
@@ -0,0 +1,63 @@
+use super::*;
+
+#[test]
+fn silent_until_beeping_test() {
+    let mut tone = Tone::new(AudioConfig::default(), 44100);
+    let mut out = [1.0f32; 256];
+
+    tone.fill(&mut out);
+    assert!(out.iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn fade_in_and_out_test() {
+    let config = AudioConfig { frequency: 100.0, volume: 0.5, waveform: Waveform::Square };
+    let mut tone = Tone::new(config, 10000);
+    let mut out = [0.0f32; 200];
+
+    tone.set_beeping(true);
+    tone.fill(&mut out);
+    // the 5ms ramp lasts 50 samples at 10kHz
+    assert!((out[0] - 0.01).abs() < 1e-4);
+    assert!((out[24] - 0.25).abs() < 1e-4);
+    assert!((out[49] - 0.5).abs() < 1e-4);
+    // second half of the first period is negative
+    assert!((out[170] + 0.5).abs() < 1e-4);
+
+    tone.set_beeping(false);
+    tone.fill(&mut out);
+    assert!(out[0].abs() < 0.5 && out[0] != 0.0);
+    assert!(out[60..].iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn waveforms_test() {
+    assert_eq!(1.0, Waveform::Square.sample(0.25));
+    assert_eq!(-1.0, Waveform::Square.sample(0.75));
+    assert_eq!(1.0, Waveform::Triangle.sample(0.0));
+    assert_eq!(-1.0, Waveform::Triangle.sample(0.5));
+    assert_eq!(0.0, Waveform::Sawtooth.sample(0.5));
+    assert!((Waveform::Sine.sample(0.25) - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn pattern_test() {
+    let mut bits = [0u8; 16];
+    bits[0] = 0xF0;
+    let pattern = AudioPattern { bits, pitch: DEFAULT_PITCH };
+    assert_eq!(4000.0, pattern.rate());
+    assert_eq!(1.0, pattern.sample(0.0));
+    assert_eq!(1.0, pattern.sample(3.5 / 128.0));
+    assert_eq!(-1.0, pattern.sample(4.5 / 128.0));
+    assert_eq!(-1.0, pattern.sample(0.99));
+
+    // at 4000Hz a 4000Hz output gets one bit per sample
+    let config = AudioConfig { volume: 1.0, ..AudioConfig::default() };
+    let mut tone = Tone::new(config, 4000);
+    let mut out = [0.0f32; 400];
+    tone.set_pattern(Some(pattern));
+    tone.set_beeping(true);
+    tone.fill(&mut out);
+    assert!(out[256..260].iter().all(|&sample| sample > 0.99));
+    assert!(out[260..264].iter().all(|&sample| sample < -0.99));
+}
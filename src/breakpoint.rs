@@ -0,0 +1,423 @@
+use crate::cpu::Cpu;
+use crate::memory::MemoryAccess;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// registers a watch or a condition can read, see Register::index
+pub const REGISTER_COUNT: usize = 21;
+
+/// Every register, in the order of the snapshots and of the GDB register numbers
+pub const REGISTERS: [Register; REGISTER_COUNT] = [
+    Register::V(0x0), Register::V(0x1), Register::V(0x2), Register::V(0x3),
+    Register::V(0x4), Register::V(0x5), Register::V(0x6), Register::V(0x7),
+    Register::V(0x8), Register::V(0x9), Register::V(0xA), Register::V(0xB),
+    Register::V(0xC), Register::V(0xD), Register::V(0xE), Register::V(0xF),
+    Register::I, Register::Pc, Register::Sp, Register::DelayTimer, Register::SoundTimer,
+];
+
+/// A register of the machine, as written in conditions and debugger commands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &Cpu) -> usize {
+        match self {
+            Register::V(index) => cpu.get_register(*index) as usize,
+            Register::I => cpu.get_i(),
+            Register::Pc => cpu.get_pc(),
+            Register::Sp => cpu.get_sp(),
+            Register::DelayTimer => cpu.get_delay_timer() as usize,
+            Register::SoundTimer => cpu.get_sound_timer() as usize,
+        }
+    }
+
+    /// Whether `value` can be written to the register, V0-VF, SP and the timers only take a byte
+    pub fn check(&self, cpu: &Cpu, value: usize) -> Result<(), String> {
+        match self {
+            Register::V(_) | Register::DelayTimer | Register::SoundTimer if u8::try_from(value).is_err() => {
+                Err(format!("0x{:X} doesn't fit in a byte", value))
+            },
+            Register::Sp if value > cpu.get_stack_config().depth => Err(format!("the stack holds at most {} entries", cpu.get_stack_config().depth)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Changes the register, fails without touching it when `check` does
+    pub fn write(&self, cpu: &mut Cpu, value: usize) -> Result<(), String> {
+        self.check(cpu, value)?;
+        match self {
+            Register::V(index) => cpu.set_register(*index, value as u8),
+            Register::I => cpu.set_i(value),
+            Register::Pc => cpu.set_pc(value),
+            Register::Sp => cpu.set_sp(value).map_err(|err| err.to_string())?,
+            Register::DelayTimer => cpu.set_delay_timer(value as u8),
+            Register::SoundTimer => cpu.set_sound_timer(value as u8),
+        };
+
+        Ok(())
+    }
+
+    /// The register at `index` in REGISTERS
+    pub fn from_index(index: usize) -> Option<Register> {
+        REGISTERS.get(index).copied()
+    }
+
+    // position in a snapshot of the registers
+    fn index(&self) -> usize {
+        match self {
+            Register::V(index) => *index,
+            Register::I => 16,
+            Register::Pc => 17,
+            Register::Sp => 18,
+            Register::DelayTimer => 19,
+            Register::SoundTimer => 20,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Register, String> {
+        match text.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "SP" => Ok(Register::Sp),
+            "DT" => Ok(Register::DelayTimer),
+            "ST" => Ok(Register::SoundTimer),
+            name => match name.strip_prefix('V').map(|index| u8::from_str_radix(index, 16)) {
+                Some(Ok(index)) if name.len() == 2 => Ok(Register::V(index as usize)),
+                _ => Err(format!("unknown register '{}', expected V0-VF, I, PC, SP, DT or ST", text)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(index) => write!(f, "V{:X}", index),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+fn snapshot(cpu: &Cpu) -> [usize; REGISTER_COUNT] {
+    let mut values = [0; REGISTER_COUNT];
+    for (value, register) in values.iter_mut().zip(REGISTERS.iter()) {
+        *value = register.read(cpu);
+    }
+
+    values
+}
+
+/// Parses a number in hex, with or without 0x
+pub fn parse_hex(text: &str) -> Result<usize, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("invalid number '{}'", text))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    Register(Register),
+    Number(usize),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, String> {
+        match text.parse::<Register>() {
+            Ok(register) => Ok(Operand::Register(register)),
+            Err(_) => parse_hex(text).map(Operand::Number).map_err(|_| format!("expected a register or a number, got '{}'", text)),
+        }
+    }
+
+    fn value(&self, cpu: &Cpu) -> usize {
+        match self {
+            Operand::Register(register) => register.read(cpu),
+            Operand::Number(number) => *number,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{}", register),
+            Operand::Number(number) => write!(f, "{:X}", number),
+        }
+    }
+}
+
+// the two characters operators go first so `<=` isn't read as `<`
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+/// A condition like `V3 == 10 && I > 300`, numbers are in hex.
+/// `&&` binds tighter than `||`, there are no parentheses
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    // alternatives of comparisons that must all hold
+    clauses: Vec<Vec<(Operand, &'static str, Operand)>>,
+}
+
+impl Condition {
+    pub fn evaluate(&self, cpu: &Cpu) -> bool {
+        self.clauses.iter().any(|clause| clause.iter().all(|(left, comparison, right)| {
+            let (left, right) = (left.value(cpu), right.value(cpu));
+            match *comparison {
+                "==" => left == right,
+                "!=" => left != right,
+                "<=" => left <= right,
+                ">=" => left >= right,
+                "<" => left < right,
+                _ => left > right,
+            }
+        }))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Condition, String> {
+        let mut clauses = Vec::new();
+        for alternative in text.split("||") {
+            let mut clause = Vec::new();
+            for comparison in alternative.split("&&") {
+                let (position, operator) = COMPARISONS.iter()
+                    .find_map(|operator| comparison.find(operator).map(|position| (position, *operator)))
+                    .ok_or_else(|| format!("expected a comparison in '{}'", comparison.trim()))?;
+                let left = Operand::parse(comparison[..position].trim())?;
+                let right = Operand::parse(comparison[position + operator.len()..].trim())?;
+                clause.push((left, operator, right));
+            }
+            clauses.push(clause);
+        }
+
+        Ok(Condition {
+            clauses,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alternatives: Vec<String> = self.clauses.iter().map(|clause| {
+            clause.iter().map(|(left, comparison, right)| format!("{} {} {}", left, comparison, right)).collect::<Vec<String>>().join(" && ")
+        }).collect();
+        write!(f, "{}", alternatives.join(" || "))
+    }
+}
+
+/// Which accesses a memory watchpoint stops on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        match self {
+            Access::Read => !access.write,
+            Access::Write => access.write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+/// Where the execution stops
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// before the instruction at this address
+    Address(usize),
+    /// before any opcode matching `pattern` under `mask`, `name` is how it was written like DXYN or FX0A
+    Opcode { pattern: u16, mask: u16, name: String },
+    /// before any instruction when the condition holds
+    Condition(Condition),
+    /// after an instruction accessed a byte between `start` and `end` included
+    Memory { start: usize, end: usize, access: Access },
+    /// after an instruction changed the register
+    Register(Register),
+}
+
+impl Breakpoint {
+    /// Parses an opcode class where X, Y and N stand for any digit, like DXYN, FX0A or 00E0
+    pub fn opcode(text: &str) -> Result<Breakpoint, String> {
+        if text.len() != 4 {
+            return Err(format!("expected 4 digits in the opcode '{}'", text));
+        }
+
+        let mut pattern = 0;
+        let mut mask = 0;
+        for c in text.chars() {
+            pattern <<= 4;
+            mask <<= 4;
+            match c.to_ascii_uppercase() {
+                'X' | 'Y' | 'N' => (),
+                digit => {
+                    pattern |= digit.to_digit(16).ok_or_else(|| format!("invalid digit '{}' in the opcode '{}'", c, text))? as u16;
+                    mask |= 0xF;
+                },
+            };
+        }
+
+        Ok(Breakpoint::Opcode { pattern, mask, name: text.to_ascii_uppercase() })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(addr) => write!(f, "break at 0x{:03X}", addr),
+            Breakpoint::Opcode { name, .. } => write!(f, "break on {}", name),
+            Breakpoint::Condition(condition) => write!(f, "break if {}", condition),
+            Breakpoint::Memory { start, end, access } => {
+                let access = match access {
+                    Access::Read => "reads",
+                    Access::Write => "writes",
+                    Access::ReadWrite => "accesses",
+                };
+                if start == end {
+                    write!(f, "watch {} of 0x{:03X}", access, start)
+                } else {
+                    write!(f, "watch {} of 0x{:03X}-0x{:03X}", access, start, end)
+                }
+            },
+            Breakpoint::Register(register) => write!(f, "watch {}", register),
+        }
+    }
+}
+
+/// Why the execution stopped, `id` is the breakpoint that was hit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BreakReason {
+    /// a breakpoint stopped the execution before the instruction at `pc`
+    Before { id: usize, pc: usize },
+    /// the instruction at `pc` accessed a watched byte
+    Memory { id: usize, pc: usize, access: MemoryAccess },
+    /// the instruction at `pc` changed a watched register
+    Register { id: usize, pc: usize, register: Register, old: usize, new: usize },
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Before { id, pc } => write!(f, "#{} stopped at 0x{:03X}", id, pc),
+            BreakReason::Memory { id, pc, access } if access.write => {
+                write!(f, "#{} 0x{:03X} wrote 0x{:02X} to 0x{:03X}", id, pc, access.value, access.addr)
+            },
+            BreakReason::Memory { id, pc, access } => write!(f, "#{} 0x{:03X} read 0x{:02X} from 0x{:03X}", id, pc, access.value, access.addr),
+            BreakReason::Register { id, pc, register, old, new } => {
+                write!(f, "#{} 0x{:03X} changed {} from 0x{:02X} to 0x{:02X}", id, pc, register, old, new)
+            },
+        }
+    }
+}
+
+/// Breakpoints and watchpoints checked by the CPU around every instruction while the set isn't empty
+pub struct Breakpoints {
+    // by id, in the order they were added
+    entries: Vec<(usize, Breakpoint)>,
+    next_id: usize,
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            entries: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a breakpoint and returns its id
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push((id, breakpoint));
+
+        id
+    }
+
+    /// Removes the breakpoint `id`, false if there is none
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|(entry, _)| *entry != id);
+
+        self.entries.len() != count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, Breakpoint)> {
+        self.entries.iter()
+    }
+
+    /// Whether the memory accesses have to be recorded
+    pub fn watches_memory(&self) -> bool {
+        self.entries.iter().any(|(_, breakpoint)| matches!(breakpoint, Breakpoint::Memory { .. }))
+    }
+
+    /// The register values to compare after the instruction, None when no register is watched
+    pub fn registers_before(&self, cpu: &Cpu) -> Option<[usize; REGISTER_COUNT]> {
+        if self.entries.iter().any(|(_, breakpoint)| matches!(breakpoint, Breakpoint::Register(_))) {
+            Some(snapshot(cpu))
+        } else {
+            None
+        }
+    }
+
+    /// Checked before the instruction at the pc runs, `opcode` is the fetched opcode
+    pub fn check_before(&self, cpu: &Cpu, opcode: u16) -> Option<BreakReason> {
+        let pc = cpu.get_pc();
+        self.entries.iter().find(|(_, breakpoint)| match breakpoint {
+            Breakpoint::Address(addr) => *addr == pc,
+            Breakpoint::Opcode { pattern, mask, .. } => opcode & mask == *pattern,
+            Breakpoint::Condition(condition) => condition.evaluate(cpu),
+            _ => false,
+        }).map(|(id, _)| BreakReason::Before { id: *id, pc })
+    }
+
+    /// Checked after the instruction at `pc` ran, with the memory it accessed and the registers from registers_before
+    pub fn check_after(&self, cpu: &Cpu, pc: usize, accesses: &[MemoryAccess], before: Option<&[usize; REGISTER_COUNT]>) -> Option<BreakReason> {
+        let after = before.map(|_| snapshot(cpu));
+
+        self.entries.iter().find_map(|(id, breakpoint)| match breakpoint {
+            Breakpoint::Memory { start, end, access: kind } => accesses.iter()
+                .find(|access| access.addr >= *start && access.addr <= *end && kind.matches(access))
+                .map(|access| BreakReason::Memory { id: *id, pc, access: *access }),
+            Breakpoint::Register(register) => match (before, after) {
+                (Some(before), Some(after)) if before[register.index()] != after[register.index()] => {
+                    Some(BreakReason::Register { id: *id, pc, register: *register, old: before[register.index()], new: after[register.index()] })
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "breakpoint_test.rs"]
+mod breakpoint_test;
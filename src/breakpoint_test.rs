@@ -0,0 +1,105 @@
+use super::*;
+use crate::error::StepOutcome;
+
+fn cpu(rom: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(rom).unwrap();
+    cpu
+}
+
+fn run(cpu: &mut Cpu, steps: usize) -> Option<BreakReason> {
+    for _ in 0..steps {
+        if let StepOutcome::Break(reason) = cpu.emulate_cycle().unwrap() {
+            return Some(reason);
+        }
+    }
+    None
+}
+
+#[test]
+fn condition_test() {
+    let mut cpu = cpu(&[]);
+    cpu.set_register(3, 0x10);
+    cpu.set_i(0x301);
+
+    let holds = |text: &str, cpu: &Cpu| text.parse::<Condition>().unwrap().evaluate(cpu);
+    assert!(holds("V3 == 0x10 && I > 0x300", &cpu));
+    assert!(holds("v3==10&&i>=301", &cpu));
+    assert!(!holds("V3 != 10", &cpu));
+    assert!(holds("V3 < 10 || I <= 301", &cpu));
+    assert!(!holds("V3 < 10 || DT > 0", &cpu));
+
+    assert_eq!("expected a comparison in 'V3'", "V3".parse::<Condition>().unwrap_err());
+    assert_eq!("expected a register or a number, got 'VX'", "VX == 1".parse::<Condition>().unwrap_err());
+}
+
+#[test]
+fn opcode_test() {
+    assert_eq!(Ok(Breakpoint::Opcode { pattern: 0xD000, mask: 0xF000, name: String::from("DXYN") }), Breakpoint::opcode("dxyn"));
+    assert_eq!("break on FX0A", Breakpoint::opcode("FX0A").unwrap().to_string());
+    assert_eq!("break on 8XY6", Breakpoint::opcode("8XY6").unwrap().to_string());
+    assert_eq!("break on 00E0", Breakpoint::opcode("00E0").unwrap().to_string());
+    assert_eq!("expected 4 digits in the opcode 'DXY'", Breakpoint::opcode("DXY").unwrap_err());
+    assert_eq!("invalid digit 'Z' in the opcode 'DXYZ'", Breakpoint::opcode("DXYZ").unwrap_err());
+}
+
+#[test]
+fn break_before_test() {
+    // V0 = 1, V0 += 1, draw, jump back to the add
+    let mut cpu = cpu(&[0x60, 0x01, 0x70, 0x01, 0xD0, 0x01, 0x12, 0x02]);
+    let draw = cpu.get_breakpoints_mut().add(Breakpoint::opcode("DXYN").unwrap());
+
+    assert_eq!(Some(BreakReason::Before { id: draw, pc: 0x204 }), run(&mut cpu, 10));
+    // the draw didn't run, the next step runs it instead of stopping again
+    assert_eq!(0x204, cpu.get_pc());
+    assert_eq!(Some(BreakReason::Before { id: draw, pc: 0x204 }), run(&mut cpu, 10));
+    assert_eq!(3, cpu.get_register(0));
+
+    cpu.get_breakpoints_mut().remove(draw);
+    let condition = cpu.get_breakpoints_mut().add(Breakpoint::Condition("V0 == 6".parse().unwrap()));
+    assert_eq!(Some(BreakReason::Before { id: condition, pc: 0x204 }), run(&mut cpu, 20));
+    assert_eq!("#2 stopped at 0x204", BreakReason::Before { id: condition, pc: 0x204 }.to_string());
+    assert_eq!("break if V0 == 6 || I > 300 && DT != 0", Breakpoint::Condition("v0==0x6||i>300&&dt!=0".parse().unwrap()).to_string());
+}
+
+#[test]
+fn memory_watch_test() {
+    // I = 0x300, V0 = 123, FX33, FX55 with V0..V1, FX65 with V0, draw 1 row from 0x300
+    let mut cpu = cpu(&[0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33, 0xF1, 0x55, 0xF0, 0x65, 0xD0, 0x01]);
+    let id = cpu.get_breakpoints_mut().add(Breakpoint::Memory { start: 0x301, end: 0x302, access: Access::Write });
+
+    // FX33 writes 1, 2 and 3 from 0x300, the first byte in the range is reported
+    let write = |addr, value| MemoryAccess { addr, value, write: true };
+    assert_eq!(Some(BreakReason::Memory { id, pc: 0x204, access: write(0x301, 2) }), run(&mut cpu, 10));
+    assert_eq!(Some(BreakReason::Memory { id, pc: 0x206, access: write(0x301, 0) }), run(&mut cpu, 10));
+    assert_eq!("#1 0x206 wrote 0x00 to 0x301", BreakReason::Memory { id, pc: 0x206, access: write(0x301, 0) }.to_string());
+    // the breakpoint is reported after the instruction ran
+    assert_eq!(0x208, cpu.get_pc());
+
+    cpu.get_breakpoints_mut().remove(id);
+    let id = cpu.get_breakpoints_mut().add(Breakpoint::Memory { start: 0x300, end: 0x300, access: Access::Read });
+    let read = |addr, value| MemoryAccess { addr, value, write: false };
+    assert_eq!(Some(BreakReason::Memory { id, pc: 0x208, access: read(0x300, 0x7B) }), run(&mut cpu, 10));
+    assert_eq!(Some(BreakReason::Memory { id, pc: 0x20A, access: read(0x300, 0x7B) }), run(&mut cpu, 10));
+}
+
+#[test]
+fn fetch_is_not_a_read_test() {
+    let mut cpu = cpu(&[0x60, 0x01, 0x12, 0x00]);
+    cpu.get_breakpoints_mut().add(Breakpoint::Memory { start: 0x200, end: 0x203, access: Access::ReadWrite });
+
+    assert_eq!(None, run(&mut cpu, 10));
+}
+
+#[test]
+fn register_watch_test() {
+    // V1 = 0, I = 0x300, V1 = 0 again, I += V1, DT = V1, V1 = 2, DT = V1
+    let mut cpu = cpu(&[0x61, 0x00, 0xA3, 0x00, 0x61, 0x00, 0xF1, 0x1E, 0xF1, 0x15, 0x61, 0x02, 0xF1, 0x15]);
+    let i = cpu.get_breakpoints_mut().add(Breakpoint::Register(Register::I));
+    let dt = cpu.get_breakpoints_mut().add(Breakpoint::Register(Register::DelayTimer));
+
+    assert_eq!(Some(BreakReason::Register { id: i, pc: 0x202, register: Register::I, old: 0, new: 0x300 }), run(&mut cpu, 10));
+    // writing the same value isn't a change
+    assert_eq!(Some(BreakReason::Register { id: dt, pc: 0x20C, register: Register::DelayTimer, old: 0, new: 2 }), run(&mut cpu, 10));
+}
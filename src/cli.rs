@@ -0,0 +1,405 @@
+use crate::audio::AudioConfig;
+use crate::display::{Rgb, DEFAULT_PALETTE};
+use crate::memory::{MemoryPolicy, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::scheduler::DEFAULT_IPS;
+use crate::rewind::DEFAULT_REWIND_SECONDS;
+use crate::trace::{TraceFilter, TraceLevel};
+use crate::cpu::{StackConfig, PROGRAM_START};
+use crate::quirks::{self, Quirks};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// folder holding the bundled games
+pub const GAMES_DIR: &str = "games";
+// game started when no ROM is given
+pub const DEFAULT_ROM: &str = "TICTAC";
+// folder holding the save state slots
+pub const STATES_DIR: &str = "states";
+
+pub const USAGE: &str = "\
+Usage: chip8 [OPTIONS] [ROM]
+       chip8 disasm [--load-address <ADDR>] <ROM>
+       chip8 asm [--load-address <ADDR>] [-o <FILE>] <SOURCE>
+
+ROM is the name of a bundled game, the path of a ROM file or - to read it from stdin (default TICTAC)
+
+Options:
+    --ips <N>               instructions executed per second (default 700)
+    --scale <N>             size of a Chip8 pixel in window pixels (default 10)
+    --fg <RRGGBB>           colour of the lit pixels (default FFFFFF)
+    --bg <RRGGBB>           colour of the background (default 000000)
+    --palette <COLOURS>     the 4 XO-CHIP colours separated by commas : background, plane 1,
+                            plane 2 and both planes (default 000000,FFFFFF,AAAAAA,555555)
+    --quirks <PROFILE>      cosmac-vip, chip-48, super-chip, xo-chip or modern (default modern)
+    --keymap <FILE>         keyboard layout file, lines like `Q = 4`
+    --load-address <ADDR>   address the ROM is loaded at, in hex (default 200)
+    --memory <POLICY>       out of bounds accesses : wrap, fault or log (default wrap)
+    --mute                  don't play the beep
+    --tone <HZ>             frequency of the beep (default 440)
+    --volume <PERCENT>      loudness of the beep (default 25)
+    --waveform <SHAPE>      square, triangle, sawtooth or sine (default square)
+    --headless              run without a window, as fast as possible
+    --debug                 run under the debugger prompt instead of a window, type help there
+    --gdb <PORT>            wait for a GDB connection on localhost:PORT and let it drive the game
+    --frames <N>            stop after N frames
+    --trace <LEVEL>         off, error, info, debug or trace (default info),
+                            trace prints every executed instruction
+    --trace-file <FILE>     write the state before every executed instruction to FILE
+    --trace-ops <CLASSES>   only trace the opcodes starting with these hex digits, like 8,D,F
+    --trace-pc <RANGE>      only trace the instructions in this address range, like 200-2FF
+    --profile <FILE>        count the executed instructions and write a report of the hot spots,
+                            opcode classes and subroutines with an annotated listing to FILE
+    --seed <N>              seed of the random number generator
+    --rewind <SECONDS>      history kept for the rewind key, 0 disables it (default 30)
+    --config <FILE>         read options from a file, lines like `quirks = cosmac-vip`,
+                            options given on the command line take precedence
+    --list                  list the bundled games
+    -h, --help              print this help";
+
+/// Everything needed to start a game
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom: String,
+    pub instructions_per_second: u32,
+    pub scale: u32,
+    // background, first plane, second plane and both planes
+    pub palette: [Rgb; 4],
+    pub quirks: Quirks,
+    pub stack: StackConfig,
+    pub memory_size: usize,
+    pub keymap: Option<String>,
+    pub load_address: usize,
+    pub memory_policy: MemoryPolicy,
+    pub mute: bool,
+    pub audio: AudioConfig,
+    pub headless: bool,
+    pub debug: bool,
+    // port of the GDB stub, replaces the window
+    pub gdb_port: Option<u16>,
+    pub frames: Option<u64>,
+    pub trace: TraceLevel,
+    pub trace_file: Option<String>,
+    pub trace_filter: TraceFilter,
+    // where the profile is written when the game stops, profiling is off without it
+    pub profile: Option<String>,
+    pub seed: Option<u64>,
+    pub rewind_seconds: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rom: DEFAULT_ROM.to_string(),
+            instructions_per_second: DEFAULT_IPS,
+            scale: 10,
+            palette: DEFAULT_PALETTE,
+            quirks: Quirks::modern(),
+            stack: StackConfig::default(),
+            memory_size: MEMORY_SIZE,
+            keymap: None,
+            load_address: PROGRAM_START,
+            memory_policy: MemoryPolicy::Wrap,
+            mute: false,
+            audio: AudioConfig::default(),
+            headless: false,
+            debug: false,
+            gdb_port: None,
+            frames: None,
+            trace: TraceLevel::Info,
+            trace_file: None,
+            trace_filter: TraceFilter::default(),
+            profile: None,
+            seed: None,
+            rewind_seconds: DEFAULT_REWIND_SECONDS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Options),
+    /// prints the listing of a ROM loaded at `load_address`
+    Disasm { rom: String, load_address: usize },
+    /// assembles `source` into `output`, next to the source with the .ch8 extension by default
+    Asm { source: String, output: Option<String>, load_address: usize },
+    List,
+    Help,
+}
+
+fn parse_number<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("invalid value '{}' for {}", value, option))
+}
+
+fn parse_range(option: &str, value: &str, min: u32, max: u32) -> Result<u32, String> {
+    let number = parse_number::<u32>(option, value)?;
+    if number < min || number > max {
+        return Err(format!("{} must be between {} and {}", option, min, max));
+    }
+
+    Ok(number)
+}
+
+/// Turns a config file made of `<option> = <value>` lines into long options, `#` starts a comment.
+/// `mute = true` becomes `--mute`, `mute = false` is dropped, `rom = PONG` gives the ROM
+pub fn parse_config(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let option = parts.next().unwrap_or("").trim();
+        let value = parts.next()
+            .ok_or_else(|| format!("config line {} : expected `<option> = <value>`", line_number + 1))?
+            .trim();
+        match (option, value) {
+            ("rom", _) => args.push(value.to_string()),
+            ("config", _) => return Err(format!("config line {} : config files can't be nested", line_number + 1)),
+            (_, "true") => args.push(format!("--{}", option)),
+            (_, "false") => (),
+            _ => args.push(format!("--{}={}", option, value)),
+        }
+    }
+
+    Ok(args)
+}
+
+fn parse_address(option: &str, value: &str) -> Result<usize, String> {
+    usize::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| format!("invalid value '{}' for {}", value, option))
+}
+
+/// Arguments of `chip8 disasm`
+fn parse_disasm_args(args: &[String]) -> Result<Command, String> {
+    let mut rom = None;
+    let mut load_address = PROGRAM_START;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--load-address" => {
+                let value = args.next().ok_or_else(|| String::from("missing value for --load-address"))?;
+                load_address = parse_address(arg, value)?;
+            },
+            _ if arg.starts_with("--load-address=") => load_address = parse_address("--load-address", &arg["--load-address=".len()..])?,
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => rom = Some(arg.clone()),
+        };
+    }
+
+    let rom = rom.ok_or_else(|| String::from("disasm needs a ROM"))?;
+    Ok(Command::Disasm { rom, load_address })
+}
+
+/// Arguments of `chip8 asm`
+fn parse_asm_args(args: &[String]) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut load_address = PROGRAM_START;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--load-address" => {
+                let value = args.next().ok_or_else(|| String::from("missing value for --load-address"))?;
+                load_address = parse_address(arg, value)?;
+            },
+            "-o" | "--output" => output = Some(args.next().ok_or_else(|| format!("missing value for {}", arg))?.clone()),
+            _ if arg.starts_with("--load-address=") => load_address = parse_address("--load-address", &arg["--load-address=".len()..])?,
+            _ if arg.starts_with("--output=") => output = Some(arg["--output=".len()..].to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if source.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => source = Some(arg.clone()),
+        };
+    }
+
+    let source = source.ok_or_else(|| String::from("asm needs a source file"))?;
+    Ok(Command::Asm { source, output, load_address })
+}
+
+/// Parses the command line arguments, without the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("disasm") => return parse_disasm_args(&args[1..]),
+        Some("asm") => return parse_asm_args(&args[1..]),
+        _ => (),
+    }
+
+    // the options of the config file go first so the command line overrides them
+    let mut all_args = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let path = match arg.strip_prefix("--config") {
+            Some("") => iter.next().ok_or_else(|| "missing value for --config".to_string())?,
+            Some(path) if path.starts_with('=') => &path[1..],
+            _ => continue,
+        };
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read config file {} : {}", path, err))?;
+        all_args.extend(parse_config(&text)?);
+    }
+    let config_args = all_args.len();
+    all_args.extend(args.iter().cloned());
+
+    let mut options = Options::default();
+    let mut rom = None;
+    let mut config_rom = None;
+    let mut args = all_args.iter().enumerate();
+
+    while let Some((position, arg)) = args.next() {
+        // both `--ips 700` and `--ips=700` are accepted
+        let (option, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (&arg[..index], Some(arg[index + 1..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => args.next().map(|(_, arg)| arg.clone()).ok_or_else(|| format!("missing value for {}", option)),
+            }
+        };
+
+        match option {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list" => return Ok(Command::List),
+            "--ips" => options.instructions_per_second = parse_range(option, &value()?, 1, 1_000_000)?,
+            "--scale" => options.scale = parse_range(option, &value()?, 1, 50)?,
+            "--fg" => options.palette[1] = value()?.parse().map_err(|err| format!("{} for --fg", err))?,
+            "--bg" => options.palette[0] = value()?.parse().map_err(|err| format!("{} for --bg", err))?,
+            "--palette" => {
+                let value = value()?;
+                let colours: Vec<&str> = value.split(',').collect();
+                if colours.len() != 4 {
+                    return Err(format!("--palette expects 4 colours, got {}", colours.len()));
+                }
+                for (entry, colour) in options.palette.iter_mut().zip(colours) {
+                    *entry = colour.trim().parse().map_err(|err| format!("{} for --palette", err))?;
+                }
+            },
+            "--quirks" => {
+                let profile = value()?;
+                options.quirks = Quirks::from_name(&profile).ok_or_else(|| {
+                    format!("unknown quirk profile '{}', expected one of {}", profile, quirks::PROFILES.join(", "))
+                })?;
+                // the VIP keeps its 12 levels of stack in memory
+                options.stack = if profile == "cosmac-vip" { StackConfig::cosmac_vip() } else { StackConfig::default() };
+                // XO-CHIP programs address 64 KiB
+                options.memory_size = if profile == "xo-chip" { XO_CHIP_MEMORY_SIZE } else { MEMORY_SIZE };
+            },
+            "--config" => {
+                // already read above
+                if inline_value.is_none() {
+                    args.next();
+                }
+            },
+            "--keymap" => options.keymap = Some(value()?),
+            "--load-address" => options.load_address = parse_address(option, &value()?)?,
+            "--memory" => {
+                options.memory_policy = match value()?.as_str() {
+                    "wrap" => MemoryPolicy::Wrap,
+                    "fault" => MemoryPolicy::Fault,
+                    "log" => MemoryPolicy::Log,
+                    other => return Err(format!("unknown memory policy '{}', expected wrap, fault or log", other)),
+                };
+            },
+            "--mute" => options.mute = true,
+            "--tone" => options.audio.frequency = parse_range(option, &value()?, 20, 20_000)? as f32,
+            "--volume" => options.audio.volume = parse_range(option, &value()?, 0, 100)? as f32 / 100.0,
+            "--waveform" => options.audio.waveform = value()?.parse()?,
+            "--headless" => options.headless = true,
+            "--debug" => options.debug = true,
+            "--gdb" => options.gdb_port = Some(parse_number(option, &value()?)?),
+            "--frames" => options.frames = Some(parse_number(option, &value()?)?),
+            "--trace" => options.trace = value()?.parse()?,
+            "--trace-file" => options.trace_file = Some(value()?),
+            "--trace-ops" => options.trace_filter.classes = TraceFilter::parse_classes(&value()?)?,
+            "--trace-pc" => options.trace_filter.pc_range = TraceFilter::parse_pc_range(&value()?)?,
+            "--profile" => options.profile = Some(value()?),
+            "--seed" => options.seed = Some(parse_number(option, &value()?)?),
+            "--rewind" => options.rewind_seconds = parse_range(option, &value()?, 0, 600)?,
+            _ if option.starts_with('-') && option != "-" => return Err(format!("unknown option {}", option)),
+            // the ROM of the command line replaces the one of the config file
+            _ if position < config_args => config_rom = Some(arg.clone()),
+            _ => {
+                if rom.is_some() {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                rom = Some(arg.clone());
+            },
+        };
+    }
+
+    if let Some(rom) = rom.or(config_rom) {
+        options.rom = rom;
+    }
+    // the debugger reads its commands from stdin
+    if options.debug && options.rom == "-" {
+        return Err(String::from("--debug can't read the ROM from stdin"));
+    }
+
+    Ok(Command::Run(options))
+}
+
+/// Names of the bundled games, sorted
+pub fn list_games() -> io::Result<Vec<String>> {
+    let mut games = Vec::new();
+    for entry in fs::read_dir(GAMES_DIR)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            games.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    games.sort();
+
+    Ok(games)
+}
+
+/// File of a save state slot : `states/<ROM file name>.<slot>.state`
+pub fn state_slot_path(rom: &str, slot: u8) -> PathBuf {
+    let name = match Path::new(rom).file_name() {
+        Some(name) if rom != "-" => name.to_string_lossy().into_owned(),
+        _ => String::from("stdin"),
+    };
+
+    Path::new(STATES_DIR).join(format!("{}.{}.state", name, slot))
+}
+
+/// Parses a keymap file made of `<key name> = <hex key>` lines, `#` starts a comment.
+/// The key names are left to the frontend to interpret
+pub fn parse_keymap(text: &str) -> Result<Vec<(String, u8)>, String> {
+    let mut keymap = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |msg: &str| format!("keymap line {} : {}", line_number + 1, msg);
+        let mut parts = line.rsplitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let name = parts.next().ok_or_else(|| error("expected `<key name> = <hex key>`"))?.trim();
+        let key = match u8::from_str_radix(key.trim_start_matches("0x"), 16) {
+            Ok(key) if key < 16 => key,
+            _ => return Err(error("key must be between 0 and F")),
+        };
+        if name.is_empty() {
+            return Err(error("missing key name"));
+        }
+
+        keymap.push((name.to_string(), key));
+    }
+
+    Ok(keymap)
+}
+
+#[cfg(test)]
+#[path = "cli_test.rs"]
+mod cli_test;
@@ -0,0 +1,136 @@
+use super::*;
+
+use crate::audio::Waveform;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn default_options_test() {
+    assert_eq!(Command::Run(Options::default()), parse_args(&[]).unwrap());
+}
+
+#[test]
+fn parse_options_test() {
+    let command = parse_args(&args("--ips 1000 --scale=5 --fg 33FF66 --bg #101010 --quirks cosmac-vip --mute --headless --frames 600 --trace off --seed 42 --rewind 10 --load-address 0x600 --memory fault --gdb 1234 --profile pong.prof roms/test.ch8")).unwrap();
+    let options = match command {
+        Command::Run(options) => options,
+        other => panic!("expected options, got {:?}", other),
+    };
+
+    assert_eq!("roms/test.ch8", options.rom);
+    assert_eq!(1000, options.instructions_per_second);
+    assert_eq!(5, options.scale);
+    assert_eq!(Rgb(0x33, 0xFF, 0x66), options.palette[1]);
+    assert_eq!(Rgb(0x10, 0x10, 0x10), options.palette[0]);
+    assert_eq!(Quirks::cosmac_vip(), options.quirks);
+    assert_eq!(StackConfig::cosmac_vip(), options.stack);
+    assert!(options.mute);
+    assert!(options.headless);
+    assert_eq!(Some(600), options.frames);
+    assert_eq!(TraceLevel::Off, options.trace);
+    assert_eq!(Some(42), options.seed);
+    assert_eq!(10, options.rewind_seconds);
+    assert_eq!(0x600, options.load_address);
+    assert_eq!(MemoryPolicy::Fault, options.memory_policy);
+    assert_eq!(Some(1234), options.gdb_port);
+    assert_eq!(Some(String::from("pong.prof")), options.profile);
+}
+
+#[test]
+fn parse_audio_test() {
+    let options = match parse_args(&args("--tone 880 --volume=50 --waveform Triangle PONG")).unwrap() {
+        Command::Run(options) => options,
+        other => panic!("expected options, got {:?}", other),
+    };
+
+    assert_eq!(AudioConfig { frequency: 880.0, volume: 0.5, waveform: Waveform::Triangle }, options.audio);
+    assert_eq!("--volume must be between 0 and 100", parse_args(&args("--volume 150")).unwrap_err());
+    assert_eq!("--tone must be between 20 and 20000", parse_args(&args("--tone 5")).unwrap_err());
+    assert_eq!("unknown waveform 'noise', expected square, triangle, sawtooth or sine", parse_args(&args("--waveform noise")).unwrap_err());
+    assert_eq!(vec!["--waveform=sine"], parse_config("waveform = sine").unwrap());
+}
+
+#[test]
+fn parse_trace_test() {
+    let options = match parse_args(&args("--trace-file trace.log --trace-ops 8,D --trace-pc=200-2FF PONG")).unwrap() {
+        Command::Run(options) => options,
+        other => panic!("expected options, got {:?}", other),
+    };
+
+    assert_eq!(Some(String::from("trace.log")), options.trace_file);
+    assert_eq!(TraceFilter { classes: 1 << 0x8 | 1 << 0xD, pc_range: (0x200, 0x2FF) }, options.trace_filter);
+    assert_eq!(TraceLevel::Info, options.trace);
+    assert_eq!("invalid opcode class 'G', expected a hex digit", parse_args(&args("--trace-ops G")).unwrap_err());
+}
+
+#[test]
+fn parse_errors_test() {
+    assert_eq!(Command::Help, parse_args(&args("PONG --help")).unwrap());
+    assert_eq!(Command::List, parse_args(&args("--list")).unwrap());
+    assert_eq!("--scale must be between 1 and 50", parse_args(&args("--scale 0")).unwrap_err());
+    assert_eq!("invalid value 'fast' for --ips", parse_args(&args("--ips fast")).unwrap_err());
+    assert_eq!("missing value for --seed", parse_args(&args("--seed")).unwrap_err());
+    assert_eq!("unknown option --turbo", parse_args(&args("--turbo")).unwrap_err());
+    assert_eq!("unexpected argument BRIX", parse_args(&args("PONG BRIX")).unwrap_err());
+    assert!(parse_args(&args("--quirks chip-10")).unwrap_err().starts_with("unknown quirk profile"));
+    assert_eq!("--palette expects 4 colours, got 2", parse_args(&args("--palette 000000,FFFFFF")).unwrap_err());
+    assert!(parse_args(&args("--fg red")).is_err());
+    assert_eq!("--debug can't read the ROM from stdin", parse_args(&args("--debug -")).unwrap_err());
+    assert_eq!("invalid value '70000' for --gdb", parse_args(&args("--gdb 70000")).unwrap_err());
+}
+
+#[test]
+fn parse_config_test() {
+    let config = parse_config("# slow VIP games\nquirks = cosmac-vip\nips=500\nmute = true\nheadless = false\nrom = PONG\n").unwrap();
+    assert_eq!(vec!["--quirks=cosmac-vip", "--ips=500", "--mute", "PONG"], config);
+
+    assert_eq!("config line 1 : expected `<option> = <value>`", parse_config("mute").unwrap_err());
+
+    let path = std::env::temp_dir().join("chip8_parse_config_test.cfg");
+    fs::write(&path, "quirks = chip-48\nips = 500\nrom = PONG\n").unwrap();
+    let command = parse_args(&args(&format!("--ips 900 --config {} BRIX", path.display()))).unwrap();
+    fs::remove_file(&path).unwrap();
+    let options = match command {
+        Command::Run(options) => options,
+        other => panic!("expected options, got {:?}", other),
+    };
+
+    assert_eq!(Quirks::chip_48(), options.quirks);
+    assert_eq!(900, options.instructions_per_second);
+    assert_eq!("BRIX", options.rom);
+}
+
+#[test]
+fn parse_disasm_test() {
+    assert_eq!(Command::Disasm { rom: String::from("PONG"), load_address: 0x200 }, parse_args(&args("disasm PONG")).unwrap());
+    assert_eq!(Command::Disasm { rom: String::from("-"), load_address: 0x600 }, parse_args(&args("disasm --load-address=600 -")).unwrap());
+    assert_eq!("disasm needs a ROM", parse_args(&args("disasm")).unwrap_err());
+    assert_eq!("unknown option --ips", parse_args(&args("disasm --ips 5 PONG")).unwrap_err());
+}
+
+#[test]
+fn parse_asm_test() {
+    assert_eq!(Command::Asm { source: String::from("pong.s"), output: None, load_address: 0x200 }, parse_args(&args("asm pong.s")).unwrap());
+    assert_eq!(Command::Asm { source: String::from("pong.s"), output: Some(String::from("out.ch8")), load_address: 0x600 },
+        parse_args(&args("asm -o out.ch8 --load-address 0x600 pong.s")).unwrap());
+    assert_eq!("asm needs a source file", parse_args(&args("asm --output=out.ch8")).unwrap_err());
+    assert_eq!("missing value for -o", parse_args(&args("asm pong.s -o")).unwrap_err());
+}
+
+#[test]
+fn parse_keymap_test() {
+    let keymap = parse_keymap("# arrows\nUp = 2\nLeft=4 \n Keypad 5 = 0x5\n").unwrap();
+    assert_eq!(vec![("Up".to_string(), 2), ("Left".to_string(), 4), ("Keypad 5".to_string(), 5)], keymap);
+
+    assert_eq!("keymap line 1 : key must be between 0 and F", parse_keymap("Q = 10").unwrap_err());
+    assert_eq!("keymap line 2 : expected `<key name> = <hex key>`", parse_keymap("Q = 1\nW").unwrap_err());
+}
+
+#[test]
+fn state_slot_path_test() {
+    assert_eq!(Path::new("states/PONG.1.state"), state_slot_path("PONG", 1));
+    assert_eq!(Path::new("states/test.ch8.8.state"), state_slot_path("roms/test.ch8", 8));
+    assert_eq!(Path::new("states/stdin.2.state"), state_slot_path("-", 2));
+}
@@ -1,16 +1,81 @@
-use crate::keypad::Keypad;
-use crate::display::Display;
-use crate::fontset::{FONTSET, FONTSET_LOCATION};
-
-use rand::Rng;
-use sdl2::Sdl;
-use std::io;
+use crate::keypad::{Keypad, InputSource};
+use crate::display::{Display, VideoSink};
+use crate::fontset::{BIG_FONTSET, BIG_FONTSET_LOCATION, FONTSET, FONTSET_LOCATION};
+use crate::error::{EmulatorError, StepOutcome};
+use crate::memory::{Memory, MemoryPolicy, MEMORY_SIZE};
+use crate::audio::{AudioPattern, DEFAULT_PITCH};
+
+use crate::trace::{TraceRecord, Tracer};
+use crate::profile::Profiler;
+use crate::breakpoint::Breakpoints;
+use crate::quirks::Quirks;
+use crate::savestate::{self, SaveStateHeader, StateReader, StateWriter, SAVESTATE_VERSION};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use std::io::prelude::*;
 use std::fs::File;
+use std::path::Path;
+
+/// Execution state of the CPU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuState {
+    Running,
+    /// FX0A halted the instruction fetch until a key is pressed, `key` is the key that went down
+    WaitingForKey { register: usize, key: Option<u8> },
+    /// DXYN halted the instruction fetch until the next frame, see Quirks::display_wait
+    WaitingForVBlank,
+    /// the program ended with 00FD, nothing runs until the next initialize
+    Exited,
+}
+
+// most interpreters load the program at 0x200, right after the interpreter itself
+pub const PROGRAM_START: usize = 0x200;
+// the ETI-660 interpreter loads its programs at 0x600
+pub const ETI_660_PROGRAM_START: usize = 0x600;
+
+// deepest stack supported by the internal stack
+pub const MAX_STACK_DEPTH: usize = 16;
+
+/// Where the return addresses of 2NNN are kept
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackLocation {
+    /// in a dedicated array outside of the emulated memory
+    Internal,
+    /// in the emulated memory starting at `base`, two bytes per entry, like the COSMAC VIP interpreter
+    Memory { base: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StackConfig {
+    // how many nested calls are allowed before a stack overflow
+    pub depth: usize,
+    pub location: StackLocation,
+}
+
+impl StackConfig {
+    /// 12 levels kept at 0xEA0 in the interpreter's work area
+    pub fn cosmac_vip() -> StackConfig {
+        StackConfig {
+            depth: 12,
+            location: StackLocation::Memory { base: 0xEA0 },
+        }
+    }
+}
+
+impl Default for StackConfig {
+    /// 16 levels like CHIP-48 and later interpreters
+    fn default() -> Self {
+        StackConfig {
+            depth: MAX_STACK_DEPTH,
+            location: StackLocation::Internal,
+        }
+    }
+}
 
 pub struct Cpu {
     // RAM memory
-    memory: [u8; 4096],
+    memory: Memory,
     // registers
     vp: [u8; 16],
     // program counter
@@ -26,92 +91,425 @@ pub struct Cpu {
     // display
     display: Display,
     // stack
-    stack: [u16; 16],
-    // stack pointer
+    stack: [u16; MAX_STACK_DEPTH],
+    // stack pointer : number of entries on the stack
     sp: usize,
+    // depth and location of the stack
+    stack_config: StackConfig,
+    // address the ROM is loaded at and where the execution starts
+    load_address: usize,
+    // random number generator used by CXNN
+    rng: StdRng,
+    // writes the executed instructions when tracing is on
+    tracer: Option<Tracer>,
+    // counts the executed instructions when profiling is on
+    profiler: Option<Profiler>,
+    // checked around every instruction while not empty
+    breakpoints: Breakpoints,
+    // pc of the last break, its instruction runs on the next step instead of stopping again
+    resume_pc: Option<usize>,
     // current opcode
     opcode: u16,
+    // running or halted by FX0A
+    state: CpuState,
+    // behaviours that differ between interpreters
+    quirks: Quirks,
+    // SUPER-CHIP RPL user flags, saved and restored by FX75/FX85
+    rpl: [u8; 16],
+    // XO-CHIP audio pattern loaded by F002
+    audio_pattern: Option<[u8; 16]>,
+    // XO-CHIP pitch register set by FX3A
+    pitch: u8,
+    // hash of the loaded ROM, save states taken with another ROM are rejected
+    rom_hash: u64,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[allow(dead_code)]
 impl Cpu {
-    pub fn new(sdl_context: &Sdl) -> Cpu {
+    pub fn new() -> Cpu {
         Cpu {
-            memory: [0u8; 4096],
+            memory: Memory::new(MEMORY_SIZE, MemoryPolicy::Wrap),
             vp: [0u8; 16],
             pc: 0usize,
             i: 0usize,
             delay_timer: 0u8,
             sound_timer: 0u8,
-            keypad: Keypad::new(sdl_context),
-            display: Display::new(sdl_context), 
-            stack: [0u16; 16],
+            keypad: Keypad::new(),
+            display: Display::new(),
+            stack: [0u16; MAX_STACK_DEPTH],
             sp: 0usize,
+            stack_config: StackConfig::default(),
+            load_address: PROGRAM_START,
+            rng: StdRng::from_entropy(),
+            tracer: None,
+            profiler: None,
+            breakpoints: Breakpoints::new(),
+            resume_pc: None,
             opcode: 0u16,
+            state: CpuState::Running,
+            quirks: Quirks::default(),
+            rpl: [0u8; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rom_hash: savestate::rom_hash(&[]),
         }
     }
 
-    pub fn load_game(&mut self, path: String) -> io::Result<()> {
-        let mut file = File::open(path)?;
-        let mut buffer: Vec<u8> = Vec::new();
+    /// Copies the ROM in memory at the load address.
+    /// Fails without touching the memory when the ROM doesn't fit between the load address and the end of memory
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        let max = self.memory.len().saturating_sub(self.load_address);
+        if rom.len() > max {
+            return Err(EmulatorError::RomTooLarge { size: rom.len(), max });
+        }
 
-        file.read_to_end(&mut buffer)?;
+        for (index, buf) in rom.iter().enumerate() {
+            self.memory[index + self.load_address] = *buf;
+        }
+        self.rom_hash = savestate::rom_hash(rom);
 
-        for (index, buf) in buffer.iter().enumerate() {
-            self.memory[index + 0x200] = *buf;
+        Ok(())
+    }
+
+    /// Snapshot of the whole machine : memory, registers, timers, stack, framebuffer and key state.
+    /// The random number generator isn't part of it
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        SaveStateHeader {
+            version: SAVESTATE_VERSION,
+            rom_hash: self.rom_hash,
+            quirks: self.quirks.to_bits(),
+            memory_size: self.memory.len(),
+        }.write(&mut out);
+
+        out.put_bytes(self.memory.as_slice());
+        out.put_bytes(&self.vp);
+        out.put_u32(self.pc as u32);
+        out.put_u32(self.i as u32);
+        out.put_u8(self.delay_timer);
+        out.put_u8(self.sound_timer);
+        out.put_u16(self.opcode);
+        out.put_u32(self.load_address as u32);
+
+        out.put_u8(self.stack_config.depth as u8);
+        match self.stack_config.location {
+            StackLocation::Internal => out.put_u8(0),
+            StackLocation::Memory { base } => {
+                out.put_u8(1);
+                out.put_u32(base as u32);
+            },
+        };
+        out.put_u8(self.sp as u8);
+        for entry in self.stack.iter() {
+            out.put_u16(*entry);
+        }
+
+        match self.state {
+            CpuState::Running => out.put_u8(0),
+            CpuState::WaitingForKey { register, key } => {
+                out.put_u8(1);
+                out.put_u8(register as u8);
+                out.put_u8(key.unwrap_or(0xFF));
+            },
+            CpuState::WaitingForVBlank => out.put_u8(2),
+            CpuState::Exited => out.put_u8(3),
+        };
+
+        self.display.write_state(&mut out);
+        self.keypad.write_state(&mut out);
+        out.put_bytes(&self.rpl);
+        match self.audio_pattern {
+            Some(pattern) => {
+                out.put_u8(1);
+                out.put_bytes(&pattern);
+            },
+            None => out.put_u8(0),
+        };
+        out.put_u8(self.pitch);
+
+        out.into_bytes()
+    }
+
+    /// Restores a snapshot made by save_state.
+    /// States of another ROM, quirk profile or memory size are rejected and the CPU is left untouched
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let mut input = StateReader::new(data);
+        let header = SaveStateHeader::read(&mut input)?;
+        if header.rom_hash != self.rom_hash {
+            return Err(EmulatorError::IncompatibleSaveState(String::from("it was taken with another ROM")));
+        }
+        if header.quirks != self.quirks.to_bits() {
+            return Err(EmulatorError::IncompatibleSaveState(String::from("it was taken with another quirk profile")));
+        }
+        if header.memory_size != self.memory.len() {
+            return Err(EmulatorError::IncompatibleSaveState(format!("it has {} bytes of memory instead of {}", header.memory_size, self.memory.len())));
         }
 
+        // everything is decoded before the CPU changes so a bad state leaves it as it was
+        let mut memory = Memory::new(header.memory_size, self.memory.get_policy());
+        for (index, byte) in input.get_bytes(header.memory_size)?.iter().enumerate() {
+            memory[index] = *byte;
+        }
+        let mut vp = [0u8; 16];
+        vp.copy_from_slice(input.get_bytes(16)?);
+        let pc = input.get_u32()? as usize;
+        let i = input.get_u32()? as usize;
+        let delay_timer = input.get_u8()?;
+        let sound_timer = input.get_u8()?;
+        let opcode = input.get_u16()?;
+        let load_address = input.get_u32()? as usize;
+
+        let depth = input.get_u8()? as usize;
+        let location = match input.get_u8()? {
+            0 => StackLocation::Internal,
+            1 => StackLocation::Memory { base: input.get_u32()? as usize },
+            other => return Err(EmulatorError::InvalidSaveState(format!("unknown stack location {}", other))),
+        };
+        let sp = input.get_u8()? as usize;
+        let mut stack = [0u16; MAX_STACK_DEPTH];
+        for entry in stack.iter_mut() {
+            *entry = input.get_u16()?;
+        }
+
+        let state = match input.get_u8()? {
+            0 => CpuState::Running,
+            1 => {
+                let register = (input.get_u8()? & 0xF) as usize;
+                let key = match input.get_u8()? {
+                    0xFF => None,
+                    key => Some(key & 0xF),
+                };
+                CpuState::WaitingForKey { register, key }
+            },
+            2 => CpuState::WaitingForVBlank,
+            3 => CpuState::Exited,
+            other => return Err(EmulatorError::InvalidSaveState(format!("unknown cpu state {}", other))),
+        };
+
+        let display = Display::read_state(&mut input)?;
+        let keypad = Keypad::read_state(&mut input)?;
+        let mut rpl = [0u8; 16];
+        rpl.copy_from_slice(input.get_bytes(16)?);
+        let audio_pattern = match input.get_u8()? {
+            0 => None,
+            _ => {
+                let mut pattern = [0u8; 16];
+                pattern.copy_from_slice(input.get_bytes(16)?);
+                Some(pattern)
+            },
+        };
+        let pitch = input.get_u8()?;
+        input.finish()?;
+
+        let stack_config = StackConfig { depth, location };
+        if sp > depth {
+            return Err(EmulatorError::InvalidSaveState(format!("stack pointer {} is deeper than the stack", sp)));
+        }
+
+        // nothing can fail past this point
+        self.set_stack_config(stack_config).map_err(|err| EmulatorError::InvalidSaveState(format!("bad stack, {}", err)))?;
+
+        self.memory = memory;
+        self.vp = vp;
+        self.pc = pc;
+        self.i = i;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.opcode = opcode;
+        self.load_address = load_address;
+        self.sp = sp;
+        self.stack = stack;
+        self.state = state;
+        self.display = display;
+        self.keypad = keypad;
+        self.rpl = rpl;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+
         Ok(())
     }
 
-    pub fn initialize(&mut self) -> Result<bool, String> {
-        // system expects that the application will be loaded at 0x200
-        self.pc = 0x200;
+    /// Writes save_state to a file
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> Result<(), EmulatorError> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.save_state())?;
+
+        Ok(())
+    }
+
+    /// Restores a state written by save_state_file
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), EmulatorError> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        self.load_state(&data)
+    }
+
+    /// Seeds the random number generator so CXNN gives the same numbers on every run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Traces every executed instruction through `tracer`, None turns tracing off
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Removes the tracer, to flush it once the execution is over
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Counts the executed instructions and the subroutine calls in `profiler`, None turns profiling off
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn get_profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn get_load_address(&self) -> usize {
+        self.load_address
+    }
+
+    /// Sets where the ROM is loaded and where the execution starts, takes effect on the next initialize
+    pub fn set_load_address(&mut self, load_address: usize) {
+        self.load_address = load_address;
+    }
+
+    pub fn initialize(&mut self) {
+        // the application is expected at the load address, 0x200 on most systems
+        self.pc = self.load_address;
         self.sp = 0;
         self.i =  0;
         self.opcode = 0;
-        
+        self.state = CpuState::Running;
+
         // initialize display
-        self.display.initialize();
+        self.display.clear_screen();
 
         // clear
         self.clear();
 
+        // back to the original resolution with only the first plane selected
+        self.display.set_planes(1);
+        self.display.set_hires(false);
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+
         // set fontset
         for (index, font) in FONTSET.iter().enumerate() {
             self.memory[index + FONTSET_LOCATION] = *font;
         }
+        for (index, font) in BIG_FONTSET.iter().enumerate() {
+            self.memory[index + BIG_FONTSET_LOCATION] = *font;
+        }
 
         // reset timers
         self.delay_timer = 0;
         self.sound_timer = 0;
-
-        Ok(true)
     }
 
-    pub fn emulate_cycle(&mut self) -> Result<bool, String> {
-
-        // update pressed keys
-        match self.keypad.update_pressed_keys() {
-            Ok(true) => (),
-            Ok(false) => return Ok(false),
-            Err(_) => return Err(String::new()),
+    pub fn emulate_cycle(&mut self) -> Result<StepOutcome, EmulatorError> {
+
+        // FX0A halts the fetch, the timers keep running since they are ticked by the scheduler
+        match self.state {
+            CpuState::Running => (),
+            CpuState::WaitingForKey { register, key } => {
+                self.update_key_wait(register, key);
+                return match self.state {
+                    CpuState::WaitingForKey { .. } => Ok(StepOutcome::WaitingForKey),
+                    _ => Ok(StepOutcome::Continue),
+                };
+            },
+            // nothing runs until tick_timers starts the next frame
+            CpuState::WaitingForVBlank => return Ok(StepOutcome::Continue),
+            CpuState::Exited => return Ok(StepOutcome::Exit),
         };
 
         // fetch opcode -> fetch it from memory at pc address
         // take 2 bytes since each opcode is 16bites long
-        self.opcode = ((self.memory[self.pc] as u16) << 8) | (self.memory[self.pc + 1] as u16);
+        self.opcode = self.memory.fetch_u16(self.pc)?;
+
+        if self.breakpoints.is_empty() {
+            return self.execute_and_advance();
+        }
+
+        if self.resume_pc.take() != Some(self.pc) {
+            if let Some(reason) = self.breakpoints.check_before(self, self.opcode) {
+                self.resume_pc = Some(self.pc);
+                return Ok(StepOutcome::Break(reason));
+            }
+        }
+        let pc = self.pc;
+        let registers = self.breakpoints.registers_before(self);
+        self.memory.set_access_log(self.breakpoints.watches_memory());
+
+        let outcome = self.execute_and_advance()?;
+
+        let accesses = self.memory.take_accesses();
+        self.memory.set_access_log(false);
+        match self.breakpoints.check_after(self, pc, &accesses, registers.as_ref()) {
+            Some(reason) if outcome == StepOutcome::Continue => Ok(StepOutcome::Break(reason)),
+            _ => Ok(outcome),
+        }
+    }
+
+    fn execute_and_advance(&mut self) -> Result<StepOutcome, EmulatorError> {
+        if self.tracer.as_ref().is_some_and(|tracer| tracer.matches(self.pc, self.opcode)) {
+            self.trace()?;
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(self.pc, self.opcode);
+        }
 
         // execute opcode
-        self.execute_opcode()?;
+        let outcome = self.execute_opcode()?;
 
         // update program counter
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+
+        Ok(outcome)
+    }
 
-        // update timer
-        self.update_timers();
+    pub fn get_breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn get_breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Lets the instruction at the pc run on the next step even if a breakpoint stops there
+    pub fn resume(&mut self) {
+        self.resume_pc = Some(self.pc);
+    }
+
+    fn trace(&mut self) -> Result<(), EmulatorError> {
+        let record = TraceRecord {
+            pc: self.pc,
+            opcode: self.opcode,
+            v: self.vp,
+            i: self.i,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        };
+        // F000 NNNN needs the word after the opcode
+        let memory = self.memory.as_slice();
+        let bytes = &memory[self.pc.min(memory.len())..(self.pc + 4).min(memory.len())];
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.write(&record, bytes)?;
+        }
 
-        Ok(true)
+        Ok(())
     }
 
     fn clear(&mut self) {
@@ -124,12 +522,38 @@ impl Cpu {
             *v = 0;
         }
         // clear memory
-        for mem in self.memory.iter_mut() {
-            *mem = 0;
-        }
+        self.memory.clear();
     }
 
-    fn update_timers(&mut self) {
+    fn update_key_wait(&mut self, register: usize, key: Option<u8>) {
+        match key {
+            None => {
+                if let Some(pressed) = self.keypad.take_newly_pressed_key() {
+                    if self.quirks.key_wait_on_press {
+                        self.vp[register] = pressed;
+                        self.state = CpuState::Running;
+                    } else {
+                        self.state = CpuState::WaitingForKey { register, key: Some(pressed) };
+                    }
+                }
+            },
+            // like on the COSMAC VIP the wait only ends once the key is released
+            Some(pressed) => {
+                if !self.keypad.is_key_pressed(pressed) {
+                    self.vp[register] = pressed;
+                    self.state = CpuState::Running;
+                }
+            },
+        };
+    }
+
+    /// Decrements the delay and sound timers, must be called at 60Hz.
+    /// Also ends the wait of DXYN when the display wait quirk is on
+    pub fn tick_timers(&mut self) {
+        if self.state == CpuState::WaitingForVBlank {
+            self.state = CpuState::Running;
+        }
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -138,7 +562,7 @@ impl Cpu {
         }
     }
 
-    fn execute_opcode(&mut self) -> Result<bool, String> {
+    fn execute_opcode(&mut self) -> Result<StepOutcome, EmulatorError> {
         // use shifting to cast u16 to u4
         let op1 = (self.opcode & 0xF000) >> 12;
         let op2 = (self.opcode & 0x0F00) >> 8;
@@ -146,267 +570,266 @@ impl Cpu {
         let op4 = self.opcode & 0x000F;
 
         match (op1, op2, op3, op4) {
-            (0x0, 0x0, 0xE, 0x0) => {
-                return self.clear_screen();
-            },
-            (0x0, 0x0, 0xE, 0xE) => {
-                return self.return_from_subroutine();
-            },
+            (0x0, 0x0, 0xE, 0x0) => self.clear_screen(),
+            (0x0, 0x0, 0xE, 0xE) => self.return_from_subroutine(),
+            (0x0, 0x0, 0xC, n) => self.scroll_down(n),
+            (0x0, 0x0, 0xD, n) => self.scroll_up(n),
+            (0x0, 0x0, 0xF, 0xB) => self.scroll_right(),
+            (0x0, 0x0, 0xF, 0xC) => self.scroll_left(),
+            (0x0, 0x0, 0xF, 0xD) => self.exit(),
+            (0x0, 0x0, 0xF, 0xE) => self.set_resolution(false),
+            (0x0, 0x0, 0xF, 0xF) => self.set_resolution(true),
             (0x1, _, _, _) => {
                 let target_addr: u16 = self.opcode & 0x0FFF;
-                return self.jump_to_address(target_addr);
+                self.jump_to_address(target_addr)
             },
             (0x2, _, _, _) => {
                 let target_addr: u16 = self.opcode & 0x0FFF;
-                return self.call_subroutine(target_addr);
+                self.call_subroutine(target_addr)
             }
             (0x3, x, _, _) => {
                 let constant = self.opcode & 0x00FF;
-                return self.if_equal_skip(x, constant);
+                self.if_equal_skip(x, constant)
             },
             (0x4, x, _, _) => {
                 let constant = self.opcode & 0x00FF;
-                return self.if_not_equal_skip(x, constant);
-            },
-            (0x5, x, y, _) => {
-                return self.if_registers_equal_skip(x, y);
+                self.if_not_equal_skip(x, constant)
             },
+            (0x5, x, y, 0) => self.if_registers_equal_skip(x, y),
+            (0x5, x, y, 2) => self.save_register_range(x, y),
+            (0x5, x, y, 3) => self.load_register_range(x, y),
             (0x6, x, _, _) => {
                 let nn = 0x00FF & self.opcode;
-                return self.assign_to_reg(x, nn);
+                self.assign_to_reg(x, nn)
             },
             (0x7, x, _, _) => {
                 let nn = 0x00FF & self.opcode;
-                return self.add_to_reg(x, nn);
-            },
-            (0x8, x, y, 0) => {
-                return self.set_reg(x, y);
-            },
-            (0x8, x, y, 1) => {
-                return self.bitwise_or(x, y);
-            },
-            (0x8, x, y, 2) => {
-                return self.bitwise_and(x, y);
-            },
-            (0x8, x, y, 3) => {
-                return self.bitwise_xor(x, y);
-            },
-            (0x8, x, y, 4) => {
-                return self.reg_sum(x, y);
-            },
-            (0x8, x, y, 5) => {
-                return self.reg_sub(x, y);
-            },
-            (0x8, x, _, 6) => {
-                return self.right_shift(x);
-            },
-            (0x8, x, y, 7) => {
-                return self.reverse_sub(x, y);
-            },
-            (0x8, x, _, 0xE) => {
-                return self.left_shift(x);
-            },
-            (0x9, x, y, 0) => {
-                return self.if_reg_not_eq_skip(x, y);
+                self.add_to_reg(x, nn)
             },
+            (0x8, x, y, 0) => self.set_reg(x, y),
+            (0x8, x, y, 1) => self.bitwise_or(x, y),
+            (0x8, x, y, 2) => self.bitwise_and(x, y),
+            (0x8, x, y, 3) => self.bitwise_xor(x, y),
+            (0x8, x, y, 4) => self.reg_sum(x, y),
+            (0x8, x, y, 5) => self.reg_sub(x, y),
+            (0x8, x, y, 6) => self.right_shift(x, y),
+            (0x8, x, y, 7) => self.reverse_sub(x, y),
+            (0x8, x, y, 0xE) => self.left_shift(x, y),
+            (0x9, x, y, 0) => self.if_reg_not_eq_skip(x, y),
             (0xA, _, _, _) => {
                 let nnn = self.opcode & 0x0FFF;
-                return self.set_index_register(nnn);
+                self.set_index_register(nnn)
             },
             (0xB, _, _, _) => {
                 let nnn = self.opcode & 0x0FFF;
-                return self.jump_to_addr_plus_v0(nnn);
+                self.jump_to_addr_plus_v0(nnn)
             },
             (0xC, x, _, _) => {
                 let nn = self.opcode & 0x00FF;
-                return self.random_number_xor(x, nn);
-            },
-            (0xD, x, y, n) => {
-                return self.draw(x, y, n);
-            },
-            (0xE, x, 0x9, 0xE) => {
-                return self.if_key_pressed_skip(x);
-            },
-            (0xE, x, 0xA, 0x1) => {
-                return self.if_key_not_pressed_skip(x);
-            },
-            (0xF, x, 0x0, 0x7) => {
-                return self.set_vx_to_delay(x);
-            },
-            (0xF, x, 0x0, 0xA) => {
-                return self.wait_key_press(x);
+                self.random_number_xor(x, nn)
             },
-            (0xF, x, 0x1, 0x5) => {
-                return self.set_delay_to_vx(x);
-            },
-            (0xF, x, 0x1, 0x8) => {
-                return self.set_sound_to_vx(x);
-            },
-            (0xF, x, 0x1, 0xE) => {
-                return self.add_vx_to_i(x);
-            },
-            (0xF, x, 0x2, 0x9) => {
-                return self.set_i_to_sprite_addr(x);
-            },
-            (0xF, x, 0x3, 0x3) => {
-                return self.bcd(x);
-            },
-            (0xF, x, 0x5, 0x5) => {
-                return self.reg_dump(x);
-            },
-            (0xF, x, 0x6, 0x5) => {
-                return self.reg_load(x);
-            },
-            (_, _, _, _) => return Err(String::from(format!("Unknown opcode : {}", self.opcode))),
+            (0xD, x, y, n) => self.draw(x, y, n),
+            (0xE, x, 0x9, 0xE) => self.if_key_pressed_skip(x),
+            (0xE, x, 0xA, 0x1) => self.if_key_not_pressed_skip(x),
+            (0xF, 0x0, 0x0, 0x0) => self.long_set_index_register(),
+            (0xF, n, 0x0, 0x1) => self.select_planes(n),
+            (0xF, 0x0, 0x0, 0x2) => self.load_audio_pattern(),
+            (0xF, x, 0x0, 0x7) => self.set_vx_to_delay(x),
+            (0xF, x, 0x0, 0xA) => self.wait_key_press(x),
+            (0xF, x, 0x1, 0x5) => self.set_delay_to_vx(x),
+            (0xF, x, 0x1, 0x8) => self.set_sound_to_vx(x),
+            (0xF, x, 0x1, 0xE) => self.add_vx_to_i(x),
+            (0xF, x, 0x3, 0x0) => self.set_i_to_big_sprite_addr(x),
+            (0xF, x, 0x3, 0xA) => self.set_pitch(x),
+            (0xF, x, 0x7, 0x5) => self.save_rpl_flags(x),
+            (0xF, x, 0x8, 0x5) => self.load_rpl_flags(x),
+            (0xF, x, 0x2, 0x9) => self.set_i_to_sprite_addr(x),
+            (0xF, x, 0x3, 0x3) => self.bcd(x),
+            (0xF, x, 0x5, 0x5) => self.reg_dump(x),
+            (0xF, x, 0x6, 0x5) => self.reg_load(x),
+            (_, _, _, _) => Err(EmulatorError::UnknownOpcode { pc: self.pc, opcode: self.opcode }),
         }
     }
 
+    /// Skips the instruction after the current one, F000 NNNN is 4 bytes long
+    fn skip_next_instruction(&mut self) -> Result<(), EmulatorError> {
+        let next = self.memory.fetch_u16(self.pc.wrapping_add(2))?;
+        self.pc += if next == 0xF000 { 4 } else { 2 };
+        Ok(())
+    }
+
     /// Opcode : 00E0
     /// 
     /// Explanation : Clears the screen 
-    fn clear_screen(&mut self) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x00E0 | Explanation = clear screen", self.pc);
-        self.display.clear_screen()
+    fn clear_screen(&mut self) -> Result<StepOutcome, EmulatorError> {
+        self.display.clear_screen();
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 00EE
     /// 
     /// Explanation : Returns from a subroutine
-    /// TODO : edge case when stack is underflowed
-    fn return_from_subroutine(&mut self) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x00EE | Explanation = return actual sp = {}, calling point = {}", self.pc, self.sp, self.stack[self.sp]);
+    ///               Returning with an empty stack is a stack underflow
+    fn return_from_subroutine(&mut self) -> Result<StepOutcome, EmulatorError> {
 
-        // move to the calling point 
-        self.pc = self.stack[self.sp] as usize;
-        // pop from the stack
-        if self.sp > 0 {
-            self.sp -= 1;
+        // pop the calling point from the stack and move to it
+        self.pc = self.pop_stack()? as usize;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.leave();
         }
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 1NNN
     /// 
     /// Explanation : Jumps to address NNN
-    /// TODO : edge case when jumping outside memory
-    fn jump_to_address(&mut self, target_addr: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x1NNN | Explanation = jump to target addr , NNN = {}", self.pc, target_addr);
+    fn jump_to_address(&mut self, target_addr: u16) -> Result<StepOutcome, EmulatorError> {
         // self.pc = target_addr - 2 + 2(added in emulation_cycle)
-        self.pc = (target_addr - 2) as usize;
-        Ok(true)
+        self.pc = (target_addr as usize).wrapping_sub(2);
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 2NNN
     /// 
     /// Explanation : Calls subroutine NNN
-    fn call_subroutine(&mut self, target_addr: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x2NNN | Explanation = call subroutine , NNN = {}", self.pc, target_addr);
+    ///               Calling with every stack slot in use is a stack overflow
+    fn call_subroutine(&mut self, target_addr: u16) -> Result<StepOutcome, EmulatorError> {
         // we must update the stack with the new pc
-        self.sp += 1;
-        self.stack[self.sp] = self.pc as u16;
+        self.push_stack(self.pc as u16)?;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enter(target_addr as usize);
+        }
         // the pc will be incremented so we have to cancel that increment so the next instruction will be at target_addr
-        self.pc = (target_addr - 2) as usize;
+        self.pc = (target_addr as usize).wrapping_sub(2);
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 3XNN
     /// 
     /// Explanation : Skips the next instruction if vp[X(4bits index)] == NN(8bits constant)
-    fn if_equal_skip(&mut self, reg_index: u16, constant: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x3NNN | Explanation = if {} == {} then skip next", self.pc, constant, self.vp[reg_index as usize]);
+    fn if_equal_skip(&mut self, reg_index: u16, constant: u16) -> Result<StepOutcome, EmulatorError> {
         if (constant as u8) == self.vp[reg_index as usize] {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 4XNN
     /// 
     /// Explanation : Skips the next instruction if vp[X(4bits index)] != NN(8bits constant)
-    fn if_not_equal_skip(&mut self, reg_index: u16, constant: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x4NNN | Explanation = if {} != {} then skip next", self.pc, constant, self.vp[reg_index as usize]);
+    fn if_not_equal_skip(&mut self, reg_index: u16, constant: u16) -> Result<StepOutcome, EmulatorError> {
         if (constant as u8) != self.vp[reg_index as usize] {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 5XY0
     /// 
     /// Explanation : Skips the next instruction if vp[Y(4bits index)] != vp[Y(4bits index)]
-    fn if_registers_equal_skip(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x5XY0 | Explanation = if {} == {} then skip next", self.pc, self.vp[x as usize], self.vp[y as usize]);
+    fn if_registers_equal_skip(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         if self.vp[x as usize] == self.vp[y as usize] {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 5XY2
+    /// 
+    /// Explanation : Stores v[X] to v[Y] in memory starting at address I, in reverse order if X > Y. I won't change
+    fn save_register_range(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
+        let (x, y) = (x as usize, y as usize);
+        let count = x.abs_diff(y) + 1;
+        for offset in 0..count {
+            let register = if x <= y { x + offset } else { x - offset };
+            self.memory.write(self.i + offset, self.vp[register])?;
+        }
+
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 5XY3
+    /// 
+    /// Explanation : Fills v[X] to v[Y] from memory starting at address I, in reverse order if X > Y. I won't change
+    fn load_register_range(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
+        let (x, y) = (x as usize, y as usize);
+        let count = x.abs_diff(y) + 1;
+        for offset in 0..count {
+            let register = if x <= y { x + offset } else { x - offset };
+            self.vp[register] = self.memory.read(self.i + offset)?;
+        }
+
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 6XNN
     /// 
     /// Explanation : Sets v[X(4 bits index)] = NN(8bits constant)
-    fn assign_to_reg(&mut self, x: u16, nn: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x6XNN | Explanation =  v[{}] = {}", self.pc, x, nn);
+    fn assign_to_reg(&mut self, x: u16, nn: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] = nn as u8;
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 7XNN
     /// 
     /// Explanation : Adds NN to v[X(4 bits index)]
-    fn add_to_reg(&mut self, x: u16, nn: u16) -> Result<bool, String> {
+    fn add_to_reg(&mut self, x: u16, nn: u16) -> Result<StepOutcome, EmulatorError> {
         let x = x as usize;
 
-        println!("ProgramCounter = 0x{:02x} | Instruction = 0x7XNN | Explanation = v[{}] += {}", self.pc, x, nn);
 
         let vp_x: u16 = self.vp[x] as u16;
         let result: u16 = vp_x + nn;
         self.vp[x] = result as u8;
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY0
     /// 
     /// Explanation : v[X(4 bits)] = v[Y(4 bits)]
-    fn set_reg(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY0 : v[{}] = v[{}] , v[y] = {}", self.pc, x, y, self.vp[y as usize]);
+    fn set_reg(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] = self.vp[y as usize];
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY1
     /// 
     /// Explanation : v[X] = v[X] | v[Y]
-    fn bitwise_or(&mut self,x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY1 : v[{}] |= v[{}]", self.pc, x, y);
+    fn bitwise_or(&mut self,x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] |= self.vp[y as usize];
-        Ok(true)
+        if self.quirks.vf_reset {
+            self.vp[0xF] = 0;
+        }
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY2
     /// 
     /// Explanation : v[X] = v[X] & v[Y]
-    fn bitwise_and(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY2 : v[{}] &= v[{}]", self.pc, x, y);
+    fn bitwise_and(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] &= self.vp[y as usize];
-        Ok(true)
+        if self.quirks.vf_reset {
+            self.vp[0xF] = 0;
+        }
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY3
     /// 
-    /// Explanation : v[X] = v[X] & v[Y]
-    fn bitwise_xor(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY3 : v[{}] ^= v[{}]", self.pc, x, y);
+    /// Explanation : v[X] = v[X] ^ v[Y]
+    fn bitwise_xor(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] ^= self.vp[y as usize];
-        Ok(true)
+        if self.quirks.vf_reset {
+            self.vp[0xF] = 0;
+        }
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY4
     /// 
     /// Explanation : v[X] = v[X] + v[Y]
-    fn reg_sum(&mut self, x: u16, y: u16) -> Result<bool, String> {
+    fn reg_sum(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         let vx = self.vp[x as usize] as u16;
         let vy = self.vp[y as usize] as u16;
         let result: u16 = vx + vy;
@@ -418,16 +841,14 @@ impl Cpu {
         }
         // cast the result. If overflow occured the result will be trimmed
         self.vp[x as usize] = result as u8;
-        println!("ProgramCounter = 0x{:02x} | 0x8XY4 : v[{}] += v[{}] result {}", self.pc, x, y, result);
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY5
     /// 
     /// Explanation : v[X] = v[X] - v[Y]
-    fn reg_sub(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY5 : v[{}] -= v[{}]", self.pc, x, y);
+    fn reg_sub(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         let vx = self.vp[x as usize] as i16;
         let vy = self.vp[y as usize] as i16;
         let result: i16 = vx - vy;
@@ -440,25 +861,25 @@ impl Cpu {
         // cast the result. If overflow occured the result will be trimmed
         self.vp[x as usize] = result as u8;
         
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY6
     /// 
-    /// Explanation : v[X] = v[X] >> 1
-    fn right_shift(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY6 : v[{}] >>= 1", self.pc, x);
-        self.vp[0xF] = self.vp[x as usize] & 0x01;
-        self.vp[x as usize] = self.vp[x as usize] / 2;
-
-        Ok(true)
+    /// Explanation : v[X] = v[X] >> 1, v[F] = the bit shifted out
+    ///               With the shift quirk v[X] = v[Y] >> 1
+    fn right_shift(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
+        let source = if self.quirks.shift_uses_vy { self.vp[y as usize] } else { self.vp[x as usize] };
+        self.vp[x as usize] = source >> 1;
+        self.vp[0xF] = source & 0x01;
+
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XY7
     /// 
     /// Explanation : v[X] = v[Y] - v[X]
-    fn reverse_sub(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x8XY7 : v[{}] = v[{}] - v[{}]", self.pc, x, y, x);
+    fn reverse_sub(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         if self.vp[y as usize] > self.vp[x as usize] {
             self.vp[0xF] = 1u8;
         } else {
@@ -471,175 +892,284 @@ impl Cpu {
 
         self.vp[x as usize] = result as u8;
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 8XYE
     /// 
-    /// Explanation : v[X] = v[X] << 1
-    fn left_shift(&mut self, x: u16) -> Result<bool, String> {
+    /// Explanation : v[X] = v[X] << 1, v[F] = the bit shifted out
+    ///               With the shift quirk v[X] = v[Y] << 1
+    fn left_shift(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         let x = x as usize;
 
-        println!("ProgramCounter = 0x{:02x} | 0x8XYE : v[{}] = {} => v[{}] <<= 1", self.pc, x, self.vp[x], x);
-        self.vp[0xf] = self.vp[x] & 0x80;
-        let vp_x = self.vp[x] as u16;
-        let result: u16 = vp_x * 2u16;
-        self.vp[x] = result as u8;
+        let source = if self.quirks.shift_uses_vy { self.vp[y as usize] } else { self.vp[x] };
+        self.vp[x] = source << 1;
+        self.vp[0xF] = source >> 7;
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : 9XY0
     /// 
     /// Explanation : if v[X] != v[Y] skip next instruction
-    fn if_reg_not_eq_skip(&mut self, x: u16, y: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0x9XY0 : if v[{}] != v[{}] then skip", self.pc, x, y);
+    fn if_reg_not_eq_skip(&mut self, x: u16, y: u16) -> Result<StepOutcome, EmulatorError> {
         if self.vp[x as usize] != self.vp[y as usize] {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : ANNN
     /// 
     /// Explanation : set index register to address NNN
-    fn set_index_register(&mut self, opcode: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xANNN : I = {}", self.pc, opcode);
+    fn set_index_register(&mut self, opcode: u16) -> Result<StepOutcome, EmulatorError> {
         self.i = opcode as usize;
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : BNNN
     /// 
     /// Explanation : jumps to address NNN plus V[0]
-    fn jump_to_addr_plus_v0(&mut self, nnn: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xBNNN : pc = v[0] + {}", self.pc, nnn);
-        self.pc = (self.vp[0x0] + (nnn as u8) - 2u8) as usize;
-        Ok(true)
+    ///               With the jump quirk it jumps to XNN plus V[X]
+    fn jump_to_addr_plus_v0(&mut self, nnn: u16) -> Result<StepOutcome, EmulatorError> {
+        let register = if self.quirks.jump_uses_vx { (nnn >> 8) as usize } else { 0x0 };
+        // the pc will be incremented after the execution
+        self.pc = (nnn as usize + self.vp[register] as usize).wrapping_sub(2);
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : CXNN
     /// 
     /// Explanation : v[X] = rand() & nn
-    fn random_number_xor(&mut self, x: u16, nn: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xCXNN : v[{}] = rand() ^ {}", self.pc, x, nn);
-        let mut rng = rand::thread_rng();
-        self.vp[x as usize] = rng.gen::<u8>() & (nn as u8);
+    fn random_number_xor(&mut self, x: u16, nn: u16) -> Result<StepOutcome, EmulatorError> {
+        self.vp[x as usize] = self.rng.gen::<u8>() & (nn as u8);
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : DXYN
     /// 
-    /// Explanation : draws a sprite at (v[X], v[Y]) of size nx8
+    /// Explanation : draws a sprite at (v[X], v[Y]) of size 8xN, DXY0 draws a 16x16 sprite made of 2 bytes per row
     ///               The values of the pixels are read from memory location I; I won't change after the execution
-    ///               v[F] is set to 1 if any screen pixels are flipped from set to unset, 0 otherwise
-    fn draw(&mut self, x: u16, y: u16, n: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xDXYN : draw at ({}, {}) sprite 8x{}", self.pc, self.vp[x as usize], self.vp[y as usize], n);
-        self.vp[0xF] = 0;
-        //println!("I : {}", self.i);
-        //println!("{} \n {} \n {} \n {} \n {} \n", self.memory[self.i+0], self.memory[self.i + 1], self.memory[self.i + 2], self.memory[self.i + 3], self.memory[self.i + 4]);
+    ///               v[F] is set to 1 if any screen pixels are flipped from set to unset, 0 otherwise.
+    ///               In high resolution v[F] is the number of rows that collided or were clipped at the bottom, like SUPER-CHIP 1.1
+    ///               The sprite starts at the coordinates modulo the screen size, the part that goes past an edge
+    ///               wraps around or is clipped depending on the quirks.
+    ///               The sprite is drawn in every plane selected by FN01, one sprite after the other in memory
+    fn draw(&mut self, x: u16, y: u16, n: u16) -> Result<StepOutcome, EmulatorError> {
+
+        let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+        let x = self.vp[x as usize] as u32 % self.display.width();
+        let y = self.vp[y as usize] as u32 % self.display.height();
+        let mut collided_rows = 0;
+        let mut clipped_rows = 0;
+        // with both planes selected the sprite of the second plane follows the one of the first
+        let mut sprite = self.i;
+        for plane in [1u8, 2u8] {
+            if self.display.get_planes() & plane == 0 {
+                continue;
+            }
 
-        let x = self.vp[x as usize] as u32;
-        for byte in 0..n {
-            let y = self.vp[y as usize] as u32 + byte as u32;
-            let buff = self.memory[self.i + (byte as usize)];
-            match self.display.draw(x, y, buff) {
-                Ok(true) => self.vp[0xF] = 1,
-                Ok(false) => (),
-                Err(err) => return Err(err),
-            };
+            for byte in 0..rows {
+                let mut row = y + byte as u32;
+                if row >= self.display.height() {
+                    if self.quirks.clip_sprites {
+                        clipped_rows = rows - byte;
+                        break;
+                    }
+                    row %= self.display.height();
+                }
+
+                let mut collision = false;
+                for col in 0..bytes_per_row {
+                    let buff = self.memory.read(sprite + byte * bytes_per_row + col)?;
+                    let left = x + 8 * col as u32;
+                    if self.quirks.clip_sprites && left >= self.display.width() {
+                        break;
+                    }
+                    collision |= self.display.draw(left % self.display.width(), row, buff, plane, self.quirks.clip_sprites);
+                }
+                if collision {
+                    collided_rows += 1;
+                }
+            }
+            sprite += rows * bytes_per_row;
         }
 
-        Ok(true)
+        self.vp[0xF] = if self.display.is_hires() {
+            (collided_rows + clipped_rows) as u8
+        } else {
+            (collided_rows > 0) as u8
+        };
+
+        if self.quirks.display_wait {
+            self.state = CpuState::WaitingForVBlank;
+        }
+
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : EX9E
     /// 
     /// Explanation : if the key stored in v[X] is pressed skip next instruction
-    fn if_key_pressed_skip(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xEX9E : if key {} is pressed skip inst", self.pc, self.vp[x as usize]);
+    fn if_key_pressed_skip(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         if self.keypad.is_key_pressed(self.vp[x as usize]) {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : EXA1
     /// 
     /// Explanation : if the key stored in v[X] is not pressed skip next instruction
-    fn if_key_not_pressed_skip(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xEXA1 : if key {} not pressed skip inst", self.pc, self.vp[x as usize]);
+    fn if_key_not_pressed_skip(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         if !self.keypad.is_key_pressed(self.vp[x as usize]) {
-            self.pc += 2;
+            self.skip_next_instruction()?;
         }
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX07
     /// 
     /// Explanation : v[X] = delay_timer
-    fn set_vx_to_delay(&mut self, x: u16) -> Result<bool, String> {
-        println!("0xFX07 : v[{}] = delay_timer = {}", x, self.delay_timer);
+    fn set_vx_to_delay(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         self.vp[x as usize] = self.delay_timer;
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX0A
     /// 
     /// Explanation : key press is awaited and stored in v[X]
-    fn wait_key_press(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xFX0A : wait for key to be pressed", self.pc);
-        self.vp[x as usize] = match self.keypad.wait_for_key() {
-            Ok(0xFF) => return Ok(false),
-            Ok(key) => key,
-            Err(err) => return Err(err),
-        };
+    ///               The CPU stops fetching instructions until a key is pressed and released,
+    ///               timers and frames keep running in the meantime
+    fn wait_key_press(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        self.state = CpuState::WaitingForKey { register: x as usize, key: None };
 
-        Ok(true)
+        Ok(StepOutcome::WaitingForKey)
     }
 
     /// Opcode : FX15
     /// 
     /// Explanantion : delay_timer = v[X]
-    fn set_delay_to_vx(&mut self, x: u16) -> Result<bool, String> {
-        println!("0xFX15 : delay_timer = v[{}] = {}", x, self.vp[x as usize]);
+    fn set_delay_to_vx(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         self.delay_timer = self.vp[x as usize];
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX18
     /// 
     /// Explanation : sound_timer = v[X]
-    fn set_sound_to_vx(&mut self, x: u16) -> Result<bool, String> {
-        println!("0xFX18 : sound_timer = v[{}] = {}", x, self.vp[x as usize]);
+    fn set_sound_to_vx(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         self.sound_timer = self.vp[x as usize];
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX1E
     /// 
-    /// Explanation : I += v[X]
-    fn add_vx_to_i(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xFX1E : I += v[{}]", self.pc, x);
-        let i = self.i as u16;
-        let vx = self.vp[x as usize] as u16;
-        let result = i + vx;
+    /// Explanation : I += v[X], wrapping at the end of memory
+    ///               v[F] is set when I goes past 0xFFF if the quirk asks for it
+    fn add_vx_to_i(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        let result = self.i + self.vp[x as usize] as usize;
 
-        if result > 0xFFF {
-            self.vp[0xF] = 1u8;
-        } else {
-            self.vp[0xF] = 0u8;
+        if self.quirks.i_overflow_sets_vf {
+            self.vp[0xF] = (result > 0xFFF) as u8;
+        }
+
+        self.i = result % self.memory.len();
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 00CN
+    /// 
+    /// Explanation : scrolls the display N rows down
+    fn scroll_down(&mut self, n: u16) -> Result<StepOutcome, EmulatorError> {
+        self.display.scroll(0, n as i32);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 00DN
+    /// 
+    /// Explanation : scrolls the display N rows up
+    fn scroll_up(&mut self, n: u16) -> Result<StepOutcome, EmulatorError> {
+        self.display.scroll(0, -(n as i32));
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 00FB
+    /// 
+    /// Explanation : scrolls the display 4 columns right
+    fn scroll_right(&mut self) -> Result<StepOutcome, EmulatorError> {
+        self.display.scroll(4, 0);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 00FC
+    /// 
+    /// Explanation : scrolls the display 4 columns left
+    fn scroll_left(&mut self) -> Result<StepOutcome, EmulatorError> {
+        self.display.scroll(-4, 0);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : 00FD
+    /// 
+    /// Explanation : exits the interpreter, the CPU stops until the next initialize
+    fn exit(&mut self) -> Result<StepOutcome, EmulatorError> {
+        self.state = CpuState::Exited;
+        Ok(StepOutcome::Exit)
+    }
+
+    /// Opcode : 00FE / 00FF
+    /// 
+    /// Explanation : switches to the 64x32 (00FE) or to the 128x64 (00FF) resolution, the screen is cleared
+    fn set_resolution(&mut self, hires: bool) -> Result<StepOutcome, EmulatorError> {
+        self.display.set_hires(hires);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : F000 NNNN
+    /// 
+    /// Explanation : sets I to the 16 bits address NNNN stored after the opcode
+    fn long_set_index_register(&mut self) -> Result<StepOutcome, EmulatorError> {
+        let nnnn = self.memory.fetch_u16(self.pc.wrapping_add(2))?;
+        self.i = nnnn as usize;
+        // skip NNNN, the pc will be incremented past the opcode after the execution
+        self.pc = self.pc.wrapping_add(2);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : FN01
+    /// 
+    /// Explanation : selects the planes N (0 to 3) used by drawing, clearing and scrolling
+    fn select_planes(&mut self, n: u16) -> Result<StepOutcome, EmulatorError> {
+        self.display.set_planes(n as u8);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : F002
+    /// 
+    /// Explanation : loads the 16 bytes at I into the audio pattern buffer
+    fn load_audio_pattern(&mut self) -> Result<StepOutcome, EmulatorError> {
+        let mut pattern = [0u8; 16];
+        for (index, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.memory.read(self.i + index)?;
         }
+        self.audio_pattern = Some(pattern);
+        Ok(StepOutcome::Continue)
+    }
 
-        self.i = result as usize;
-        Ok(true)
+    /// Opcode : FX3A
+    /// 
+    /// Explanation : sets the pitch register of the audio pattern to v[X]
+    fn set_pitch(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        self.pitch = self.vp[x as usize];
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX29
     /// 
     /// Explanation : sets I to the location of the sprite for the character v[x]
-    fn set_i_to_sprite_addr(&mut self, x: u16) -> Result<bool, String> {
+    fn set_i_to_sprite_addr(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         /* ----------------------
         *  0      0x50, 0x64, ...
         *  ----------------------
@@ -647,10 +1177,18 @@ impl Cpu {
         *  Each character is represented by a 4x5 font so each character takes 40 bits. 
         *  We are iterating through memory with 5 bytes jumps
         */
-        println!("ProgramCounter = 0x{:02x} | 0xFX29 : I = location of char {} = {}", self.pc, self.vp[x as usize], FONTSET_LOCATION + 5 * (self.vp[x as usize]) as usize);
         let index = FONTSET_LOCATION + 5 * (self.vp[x as usize] as usize);
-        self.i = index as usize;
-        Ok(true)
+        self.i = index;
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : FX30
+    /// 
+    /// Explanation : sets I to the location of the 8x10 sprite for the digit v[x]
+    fn set_i_to_big_sprite_addr(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        let index = BIG_FONTSET_LOCATION + 10 * (self.vp[x as usize] as usize & 0xF);
+        self.i = index;
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX33
@@ -659,64 +1197,260 @@ impl Cpu {
     ///               *(I+0)=BCD(3);
     ///               *(I+1)=BCD(2);
     ///               *(I+2)=BCD(1); 
-    fn bcd(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xFX33 : bcd", self.pc);
-        self.memory[self.i]     = (self.vp[x as usize] / 100) as u8;
-        self.memory[self.i + 1] = ((self.vp[x as usize] / 10) % 10) as u8;
-        self.memory[self.i + 2] = (self.vp[x as usize] % 10) as u8;
+    fn bcd(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        self.memory.write(self.i,     self.vp[x as usize] / 100)?;
+        self.memory.write(self.i + 1, (self.vp[x as usize] / 10) % 10)?;
+        self.memory.write(self.i + 2, self.vp[x as usize] % 10)?;
 
-        Ok(true)
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX55
     /// 
     /// Explanation : Stores v[0] to v[x] in memory starting at address I
-    fn reg_dump(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xFX55 : reg dump for {}", self.pc, x);
+    ///               With the load/store quirk I is left pointing after v[x]
+    fn reg_dump(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         for (index, v) in self.vp.iter().enumerate() {
-            self.memory[self.i + index] = *v;
+            self.memory.write(self.i + index, *v)?;
             if index as u16 == x {
                 break;
             }
         }
 
-        Ok(true)
+        if self.quirks.load_store_increments_i {
+            self.i += x as usize + 1;
+        }
+
+        Ok(StepOutcome::Continue)
     }
 
     /// Opcode : FX65
     /// 
-    /// Explanation : Fills v[0] to v[0xF] with values from memory starting with I
-    fn reg_load(&mut self, x: u16) -> Result<bool, String> {
-        println!("ProgramCounter = 0x{:02x} | 0xFX65 : reg load for {}", self.pc, x);
+    /// Explanation : Fills v[0] to v[x] with values from memory starting with I
+    ///               With the load/store quirk I is left pointing after v[x]
+    fn reg_load(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
         for (index, v) in self.vp.iter_mut().enumerate() {
-            *v = self.memory[self.i + index];
+            *v = self.memory.read(self.i + index)?;
             if index as u16 == x {
                 break;
             }
         }
 
-        Ok(true)
+        if self.quirks.load_store_increments_i {
+            self.i += x as usize + 1;
+        }
+
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : FX75
+    /// 
+    /// Explanation : Stores v[0] to v[x] in the RPL user flags
+    fn save_rpl_flags(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        let count = x as usize + 1;
+        self.rpl[..count].copy_from_slice(&self.vp[..count]);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Opcode : FX85
+    /// 
+    /// Explanation : Fills v[0] to v[x] with the RPL user flags
+    fn load_rpl_flags(&mut self, x: u16) -> Result<StepOutcome, EmulatorError> {
+        let count = x as usize + 1;
+        self.vp[..count].copy_from_slice(&self.rpl[..count]);
+        Ok(StepOutcome::Continue)
+    }
+
+    /// The XO-CHIP audio pattern to play while the sound timer is non zero, None until F002 loads one
+    pub fn get_audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_pattern.map(|bits| AudioPattern { bits, pitch: self.pitch })
+    }
+
+    /// Changes the size of the address space, XO-CHIP programs need XO_CHIP_MEMORY_SIZE.
+    /// The memory is cleared, takes effect before initialize and load_rom
+    pub fn set_memory_size(&mut self, size: usize) {
+        self.memory = Memory::new(size, self.memory.get_policy());
+    }
+
+    /// The RPL user flags, frontends can keep them between runs like the HP-48 did
+    pub fn get_rpl_flags(&self) -> [u8; 16] {
+        self.rpl
     }
 
-    pub fn get_display(&self) -> [u8; 32 * 64] {
+    pub fn set_rpl_flags(&mut self, rpl: [u8; 16]) {
+        self.rpl = rpl;
+    }
+
+    /// Copy of the pixels of the current resolution
+    pub fn get_display(&self) -> Vec<u8> {
         self.display.get_display()
     }
 
-    pub fn set_display(&mut self, display: [u8; 32 * 64]) {
+    /// Replaces the pixels of the current resolution, `display` must hold width * height pixels
+    pub fn set_display(&mut self, display: &[u8]) {
         self.display.set_display(display);
     }
 
+    /// Hands the framebuffer to the video sink if it changed since the last presented frame
+    pub fn present(&mut self, video: &mut dyn VideoSink) -> Result<(), EmulatorError> {
+        if self.display.take_dirty() {
+            video.present(&self.display).map_err(EmulatorError::Backend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Polls the input source and latches its key state
+    pub fn poll_input(&mut self, input: &mut dyn InputSource) -> Result<StepOutcome, EmulatorError> {
+        if !input.poll().map_err(EmulatorError::Backend)? {
+            return Ok(StepOutcome::Quit);
+        }
+        self.keypad.set_keys(input.keys());
+
+        Ok(StepOutcome::Continue)
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Address of the next instruction
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn get_i(&self) -> usize {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: usize) {
+        self.i = i;
+    }
+
+    /// Number of return addresses on the stack
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    /// Moves the stack pointer, the entries below it keep whatever they held
+    pub fn set_sp(&mut self, sp: usize) -> Result<(), EmulatorError> {
+        if sp > self.stack_config.depth {
+            return Err(EmulatorError::StackOverflow { pc: self.pc });
+        }
+        self.sp = sp;
+
+        Ok(())
+    }
+
+    pub fn get_state(&self) -> CpuState {
+        self.state
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
     pub fn get_keypad(&mut self) -> &mut Keypad {
         &mut self.keypad
     }
 
-    pub fn push_stack(&mut self, new_pc: u16) {
+    pub fn push_stack(&mut self, new_pc: u16) -> Result<(), EmulatorError> {
+        if self.sp >= self.stack_config.depth {
+            return Err(EmulatorError::StackOverflow { pc: self.pc });
+        }
+
+        match self.stack_config.location {
+            StackLocation::Internal => self.stack[self.sp] = new_pc,
+            StackLocation::Memory { base } => self.memory.write_u16(base + 2 * self.sp, new_pc)?,
+        };
         self.sp += 1;
-        self.stack[self.sp] = new_pc;
+
+        Ok(())
+    }
+
+    pub fn pop_stack(&mut self) -> Result<u16, EmulatorError> {
+        let top = self.top_stack().ok_or(EmulatorError::StackUnderflow { pc: self.pc })?;
+        self.sp -= 1;
+
+        Ok(top)
+    }
+
+    pub fn top_stack(&self) -> Option<u16> {
+        if self.sp == 0 {
+            return None;
+        }
+
+        Some(self.stack_entry(self.sp - 1))
+    }
+
+    /// Return addresses currently on the stack, from the outermost call to the innermost
+    pub fn get_stack(&self) -> Vec<u16> {
+        (0..self.sp).map(|index| self.stack_entry(index)).collect()
     }
 
-    pub fn top_stack(&self) -> u16 {
-        self.stack[self.sp]
+    // the return address at `index` from the bottom of the stack
+    fn stack_entry(&self, index: usize) -> u16 {
+        match self.stack_config.location {
+            StackLocation::Internal => self.stack[index],
+            // set_stack_config made sure the whole stack fits in memory
+            StackLocation::Memory { base } => ((self.memory[base + 2 * index] as u16) << 8) | self.memory[base + 2 * index + 1] as u16,
+        }
+    }
+
+    pub fn get_memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn get_memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Chooses what happens when an opcode accesses memory out of bounds
+    pub fn set_memory_policy(&mut self, policy: MemoryPolicy) {
+        self.memory.set_policy(policy);
+    }
+
+    pub fn get_stack_config(&self) -> StackConfig {
+        self.stack_config
+    }
+
+    /// Changes the depth and location of the stack, the stack is emptied.
+    /// The internal stack holds at most MAX_STACK_DEPTH entries and a memory stack must fit in the 4K of memory
+    pub fn set_stack_config(&mut self, config: StackConfig) -> Result<(), EmulatorError> {
+        match config.location {
+            StackLocation::Internal if config.depth > MAX_STACK_DEPTH => {
+                return Err(EmulatorError::StackOverflow { pc: self.pc });
+            },
+            StackLocation::Memory { base } if base + 2 * config.depth > self.memory.len() => {
+                return Err(EmulatorError::MemoryOutOfBounds { addr: base + 2 * config.depth - 1 });
+            },
+            _ => (),
+        };
+
+        self.stack_config = config;
+        self.sp = 0;
+
+        Ok(())
     }
 
     pub fn set_register(&mut self, index: usize, value: u8) {
@@ -729,5 +1463,6 @@ impl Cpu {
  }
 
 #[cfg(test)]
-#[path = ".\\cpu_test.rs"]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
+#[path = "cpu_test.rs"]
 mod cpu_test;
\ No newline at end of file
@@ -1,17 +1,22 @@
 use super::*;
-use sdl2;
+use crate::display::MemoryVideo;
+use crate::keypad::ScriptedInput;
+use crate::trace::TraceFilter;
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+
+use std::fs;
 
 #[test]
 fn op_00e0_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let new_display = [1; 32 * 64]; 
-    cpu.set_display(new_display);
+    cpu.set_display(&new_display);
 
     assert!(new_display.iter().zip(cpu.get_display().iter()).all(|(a, b)| a == b), "Display was not set properly");
     
     cpu.opcode = 0x00E0;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert!([0; 32 * 64].iter().zip(cpu.get_display().iter()).all(|(a, b)| a == b), "Opcode 00e0 did not clear screen");
 }
 
@@ -19,50 +24,105 @@ fn op_00e0_test() {
 fn op_00ee_test() {
     const NEW_PC: u16 = 42;
     const PC: usize = 16;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
-    cpu.push_stack(PC as u16);
-    cpu.push_stack(NEW_PC);
+    let mut cpu = Cpu::new();
+    cpu.push_stack(PC as u16).unwrap();
+    cpu.push_stack(NEW_PC).unwrap();
 
-    assert_eq!(NEW_PC, cpu.top_stack());
+    assert_eq!(Some(NEW_PC), cpu.top_stack());
 
     cpu.opcode = 0x00EE;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
-    assert_eq!(PC as u16, cpu.top_stack());
+    assert_eq!(StepOutcome::Continue, result);
+    assert_eq!(Some(PC as u16), cpu.top_stack());
     assert_eq!(NEW_PC as usize, cpu.pc);
 }
 
 #[test]
 fn op_1nnn_test() {
     const TARGET_ADDR: u16 = 0x051A;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     assert_eq!(0 as usize, cpu.pc);
 
     cpu.opcode = 0x1000 | TARGET_ADDR;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((TARGET_ADDR - 2) as usize, cpu.pc);
 }
 
 #[test]
 fn op_2nnn_test() {
     const TARGET_ADDR: u16 = 0x051A;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     cpu.pc = 0x200;
 
     cpu.opcode = 0x2000 | TARGET_ADDR;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((TARGET_ADDR - 2) as usize, cpu.pc);
-    assert_eq!(0x200, cpu.stack[1]);
+    assert_eq!(0x200, cpu.stack[0]);
     assert_eq!(1 as usize, cpu.sp);
 }
 
+#[test]
+fn stack_overflow_test() {
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x200;
+
+    cpu.opcode = 0x2200;
+    for _ in 0..MAX_STACK_DEPTH {
+        cpu.execute_opcode().unwrap();
+    }
+    assert_eq!(MAX_STACK_DEPTH, cpu.get_stack().len());
+
+    match cpu.execute_opcode() {
+        Err(EmulatorError::StackOverflow { pc }) => assert_eq!(0x1FE, pc),
+        other => panic!("expected a stack overflow, got {:?}", other),
+    };
+}
+
+#[test]
+fn stack_underflow_test() {
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x204;
+
+    cpu.opcode = 0x00EE;
+    match cpu.execute_opcode() {
+        Err(EmulatorError::StackUnderflow { pc }) => assert_eq!(0x204, pc),
+        other => panic!("expected a stack underflow, got {:?}", other),
+    };
+}
+
+#[test]
+fn cosmac_vip_stack_test() {
+    let mut cpu = Cpu::new();
+    cpu.set_stack_config(StackConfig::cosmac_vip()).unwrap();
+    cpu.pc = 0x234;
+
+    cpu.opcode = 0x2300;
+    cpu.execute_opcode().unwrap();
+    // the return address lives in the emulated memory
+    assert_eq!(0x02, cpu.memory[0xEA0]);
+    assert_eq!(0x34, cpu.memory[0xEA1]);
+    assert_eq!(vec![0x234], cpu.get_stack());
+
+    for _ in 1..12 {
+        cpu.execute_opcode().unwrap();
+    }
+    assert!(cpu.execute_opcode().is_err());
+
+    for _ in 0..12 {
+        cpu.opcode = 0x00EE;
+        cpu.execute_opcode().unwrap();
+    }
+    assert_eq!(0x234, cpu.pc);
+    assert_eq!(None, cpu.top_stack());
+}
+
 #[test]
 fn op_3xnn_equal_test() {
     let x: usize = 3;
     let nn: u8 = 6; 
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x, nn);
     assert_eq!(nn, cpu.get_register(x));
@@ -70,7 +130,7 @@ fn op_3xnn_equal_test() {
 
     cpu.opcode = 0x3000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(2, cpu.pc);
 }
 
@@ -79,7 +139,7 @@ fn op_3xnn_not_equal_test() {
     let x: usize = 3;
     let nn: u8 = 6; 
     let reg_nn: u8 = 15;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x, reg_nn);
     assert_eq!(reg_nn, cpu.get_register(x));
@@ -87,7 +147,7 @@ fn op_3xnn_not_equal_test() {
 
     cpu.opcode = 0x3000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(0, cpu.pc);
 }
 
@@ -95,7 +155,7 @@ fn op_3xnn_not_equal_test() {
 fn op_4xnn_equal_test() {
     let x: usize = 3;
     let nn: u8 = 6; 
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x, nn);
     assert_eq!(nn, cpu.get_register(x));
@@ -103,7 +163,7 @@ fn op_4xnn_equal_test() {
 
     cpu.opcode = 0x4000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(0, cpu.pc);
 }
 
@@ -112,7 +172,7 @@ fn op_4xnn_not_equal_test() {
     let x: usize = 3;
     let nn: u8 = 6; 
     let reg_nn: u8 = 15;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x, reg_nn);
     assert_eq!(reg_nn, cpu.get_register(x));
@@ -120,14 +180,14 @@ fn op_4xnn_not_equal_test() {
 
     cpu.opcode = 0x4000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(2, cpu.pc);
 }
 
 fn do_5xy0(x_val: u8, y_val: u8, expected_pc: usize) {
     let x: usize = 3;
     let y: usize = 5;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x, x_val);
     assert_eq!(x_val, cpu.get_register(x));
@@ -136,7 +196,7 @@ fn do_5xy0(x_val: u8, y_val: u8, expected_pc: usize) {
 
     cpu.opcode = 0x5000 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(expected_pc, cpu.pc);
 }
 
@@ -154,12 +214,12 @@ fn op_5xy0_not_equal_test() {
 fn op_6xnn_test() {
     let x: usize = 5;
     let nn: u8 = 10;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     assert_eq!(0u8, cpu.get_register(x));
 
     cpu.opcode = 0x6000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(nn, cpu.get_register(x));
 }
 
@@ -167,12 +227,12 @@ fn op_6xnn_test() {
 fn op_7xnn_test() {
     let x: usize = 5;
     let nn: u8 = 3;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     assert_eq!(0u8, cpu.get_register(x));
 
     cpu.opcode = 0x7000 | ((x as u16) << 8) | (nn as u16);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(nn, cpu.get_register(x));
 }
 
@@ -181,7 +241,7 @@ fn op_8xy0_test() {
     let x = 2u16;
     let y = 3u16;
     let val = 9u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     assert_eq!(0u8, cpu.get_register(x as usize));
     assert_eq!(0u8, cpu.get_register(y as usize));
@@ -192,7 +252,7 @@ fn op_8xy0_test() {
     cpu.opcode = 0x8000 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
 
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(cpu.get_register(x as usize), cpu.get_register(y as usize));
 }
 
@@ -202,7 +262,7 @@ fn op_8xy_bitwise_op_test() {
     let y = 2u8;
     let val_x = 5u8;
     let val_y = 7u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x as usize, val_x);
     assert_eq!(val_x, cpu.get_register(x as usize));
@@ -212,27 +272,27 @@ fn op_8xy_bitwise_op_test() {
 
     cpu.opcode = 0x8001 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x | val_y, cpu.get_register(x as usize));
 
     let val_x = val_x | val_y;
 
     cpu.opcode = 0x8002 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x & val_y, cpu.get_register(x as usize));
 
     let val_x = val_x & val_y;
 
     cpu.opcode = 0x8003 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x ^ val_y, cpu.get_register(x as usize));
 }
 
 #[test]
 fn op_8xy_reg_op_no_overflow_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let x = 1u8;
     let y = 3u8;
     let val_x = 150;
@@ -245,7 +305,7 @@ fn op_8xy_reg_op_no_overflow_test() {
 
     cpu.opcode = 0x8004 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x + val_y, cpu.get_register(x as usize));
     assert_eq!(0u8, cpu.get_register(0xF));
 
@@ -253,14 +313,14 @@ fn op_8xy_reg_op_no_overflow_test() {
 
     cpu.opcode = 0x8005 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x - val_y, cpu.get_register(x as usize));
     assert_eq!(1u8, cpu.get_register(0xF));
 }
 
 #[test]
 fn op_8xy_reg_op_overflow_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let x = 1u8;
     let y = 3u8;
     let val_x = 150i16;
@@ -273,7 +333,7 @@ fn op_8xy_reg_op_overflow_test() {
 
     cpu.opcode = 0x8004 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((val_x + val_y) as u8, cpu.get_register(x as usize));
     assert_eq!(1u8, cpu.get_register(0xF));
 
@@ -281,7 +341,7 @@ fn op_8xy_reg_op_overflow_test() {
 
     cpu.opcode = 0x8005 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((val_x - val_y) as u8, cpu.get_register(x as usize));
     assert_eq!(0u8, cpu.get_register(0xF));
 }
@@ -292,7 +352,7 @@ fn op_8xy7_reverse_sub_no_underflow_test() {
     let y = 5u8;
     let val_x = 55u8;
     let val_y = 65u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     
     cpu.set_register(x as usize, val_x);
     assert_eq!(val_x, cpu.get_register(x as usize));
@@ -302,7 +362,7 @@ fn op_8xy7_reverse_sub_no_underflow_test() {
     cpu.opcode = 0x8007 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
 
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_y - val_x, cpu.get_register(x as usize));
     assert_eq!(1u8, cpu.get_register(0xF));
 }
@@ -313,7 +373,7 @@ fn op_8xy7_reverse_sub_underflow_test() {
     let y = 5u8;
     let val_x = 75i16;
     let val_y = 65i16;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     
     cpu.set_register(x as usize, val_x as u8);
     assert_eq!(val_x as u8, cpu.get_register(x as usize));
@@ -323,7 +383,7 @@ fn op_8xy7_reverse_sub_underflow_test() {
     cpu.opcode = 0x8007 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
 
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((val_y - val_x) as u8, cpu.get_register(x as usize));
     assert_eq!(0u8, cpu.get_register(0xF));
 }
@@ -334,7 +394,7 @@ fn op_8xy4_6_shift_test() {
     let y = 5u8;
     let val_x = 53u8;
     let val_y = 10u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x as usize, val_x);
     assert_eq!(val_x, cpu.get_register(x as usize));
@@ -344,14 +404,14 @@ fn op_8xy4_6_shift_test() {
     cpu.opcode = 0x8006 | ((x as u16) << 8);
     let result = cpu.execute_opcode().unwrap();
 
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x & 0x01, cpu.get_register(0xF));
     assert_eq!(val_x >> 1, cpu.get_register(x as usize));
 
     cpu.opcode = 0x800E | ((y as u16) << 8);
     let result = cpu.execute_opcode().unwrap();
 
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_y & 0x80, cpu.get_register(0xF));
     assert_eq!(val_y << 1, cpu.get_register(y as usize));
 }
@@ -363,7 +423,7 @@ fn op_9xy0_test() {
     let val_x = 30u8;
     let val_y = 30u8;
     let new_val_y = 35u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
 
     cpu.set_register(x as usize, val_x);
     assert_eq!(val_x, cpu.get_register(x as usize));
@@ -372,7 +432,7 @@ fn op_9xy0_test() {
 
     cpu.opcode = 0x9000 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(0usize, cpu.pc);
 
     cpu.set_register(y as usize, new_val_y);
@@ -380,26 +440,26 @@ fn op_9xy0_test() {
 
     cpu.opcode = 0x9000 | ((x as u16) << 8) | ((y as u16) << 4);
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(2usize, cpu.pc);
 
 }
 
 #[test]
 fn op_annn_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     cpu.opcode = 0xA523u16;
 
     assert_eq!(0usize, cpu.i);
 
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!((cpu.opcode & 0x0FFF) as usize, cpu.i);
 }
 
 #[test]
 fn op_bnnn_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     cpu.opcode = 0xB009u16;
     let val_vo = 5u16;
 
@@ -408,24 +468,24 @@ fn op_bnnn_test() {
     assert_eq!(val_vo as u8, cpu.get_register(0usize));
 
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(((cpu.opcode & 0x0FFF) + val_vo - 2) as usize, cpu.pc);
 }
 
 #[test]
 fn op_fx07_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     cpu.delay_timer = 15;
 
     cpu.opcode = 0xF307;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(cpu.delay_timer, cpu.get_register(0x3 as usize));
 }
 
 #[test]
 fn op_fx15_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let x = 10u8;
     let val_x = 50u8;
 
@@ -434,13 +494,13 @@ fn op_fx15_test() {
 
     cpu.opcode = 0xFA15;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x, cpu.delay_timer);
 }
 
 #[test]
 fn op_fx18_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let x = 10u8;
     let val_x = 50u8;
 
@@ -449,7 +509,7 @@ fn op_fx18_test() {
 
     cpu.opcode = 0xFA18;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x, cpu.sound_timer);
 }
 
@@ -457,7 +517,7 @@ fn op_fx18_test() {
 fn op_fx1e_test() {
     let x = 3u8;
     let val_x = 5u8;
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     assert_eq!(0usize, cpu.i);
 
     cpu.set_register(x as usize, val_x);
@@ -465,22 +525,568 @@ fn op_fx1e_test() {
 
     cpu.opcode = 0xF31E;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(val_x as usize, cpu.i);
     assert_eq!(0u8, cpu.get_register(0xF));
 }
 
+#[test]
+fn op_fx1e_overflow_test() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0xFFE;
+    cpu.set_register(3, 5);
+    cpu.opcode = 0xF31E;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x003, cpu.i);
+    assert_eq!(1, cpu.get_register(0xF));
+
+    // XO-CHIP addresses 64 KiB, v[F] is left alone
+    let mut cpu = Cpu::new();
+    cpu.set_quirks(Quirks::xo_chip());
+    cpu.set_memory_size(XO_CHIP_MEMORY_SIZE);
+    cpu.i = 0xFFFE;
+    cpu.set_register(0xF, 7);
+    cpu.set_register(3, 5);
+    cpu.opcode = 0xF31E;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x0003, cpu.i);
+    assert_eq!(7, cpu.get_register(0xF));
+
+    cpu.i = 0x1000;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x1005, cpu.i);
+    assert_eq!(7, cpu.get_register(0xF));
+}
+
 #[test]
 fn op_fx33_test() {
-    let mut cpu = Cpu::new(&sdl2::init().unwrap());
+    let mut cpu = Cpu::new();
     let x = 3usize;
     let val_x = 152u8;
     cpu.set_register(x, val_x);
 
     cpu.opcode = 0xF333;
     let result = cpu.execute_opcode().unwrap();
-    assert_eq!(true, result);
+    assert_eq!(StepOutcome::Continue, result);
     assert_eq!(1, cpu.memory[cpu.i]);
     assert_eq!(5, cpu.memory[cpu.i + 1]);
     assert_eq!(2, cpu.memory[cpu.i + 2]);
-}
\ No newline at end of file
+}
+#[test]
+fn present_only_changed_frames_test() {
+    let mut cpu = Cpu::new();
+    let mut video = MemoryVideo::new();
+
+    // a fresh framebuffer is presented once
+    cpu.present(&mut video).unwrap();
+    cpu.present(&mut video).unwrap();
+    assert_eq!(1, video.frames().len());
+
+    // draw the font sprite for 0 at (0, 0)
+    cpu.initialize();
+    cpu.i = FONTSET_LOCATION;
+    cpu.opcode = 0xD005;
+    cpu.execute_opcode().unwrap();
+    cpu.present(&mut video).unwrap();
+
+    assert_eq!(2, video.frames().len());
+    let frame = video.last_frame().unwrap();
+    assert_eq!(&[1, 1, 1, 1, 0, 0, 0, 0], &frame[0..8]);
+    assert_eq!(&[1, 0, 0, 1, 0, 0, 0, 0], &frame[64..72]);
+}
+
+#[test]
+fn op_ex9e_exa1_scripted_input_test() {
+    let mut cpu = Cpu::new();
+    let mut input = ScriptedInput::from_script("0 down A").unwrap();
+    cpu.set_register(0x2, 0xA);
+
+    cpu.opcode = 0xE2A1;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(2, cpu.pc);
+
+    assert_eq!(StepOutcome::Continue, cpu.poll_input(&mut input).unwrap());
+
+    cpu.opcode = 0xE29E;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(4, cpu.pc);
+
+    cpu.opcode = 0xE2A1;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(4, cpu.pc);
+}
+
+fn do_fx0a(on_press: bool, script: &str, polls: usize) -> Cpu {
+    let mut cpu = Cpu::new();
+    let mut input = ScriptedInput::from_script(script).unwrap();
+    cpu.initialize();
+    cpu.set_quirks(Quirks { key_wait_on_press: on_press, ..Quirks::modern() });
+    cpu.delay_timer = 10;
+    // FX0A with X = 4 followed by an infinite loop
+    cpu.load_rom(&[0xF4, 0x0A, 0x12, 0x02]).unwrap();
+
+    for _ in 0..polls {
+        cpu.poll_input(&mut input).unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.tick_timers();
+    }
+
+    cpu
+}
+
+#[test]
+fn op_fx0a_release_test() {
+    // key 7 goes down on poll 2 and is released on poll 4
+    let cpu = do_fx0a(false, "2 down 7\n4 up 7", 4);
+    assert_eq!(CpuState::WaitingForKey { register: 4, key: Some(7) }, cpu.get_state());
+    assert_eq!(0x202, cpu.pc);
+    // timers keep running while the fetch is halted
+    assert_eq!(6, cpu.delay_timer);
+
+    let cpu = do_fx0a(false, "2 down 7\n4 up 7", 5);
+    assert_eq!(CpuState::Running, cpu.get_state());
+    assert_eq!(7, cpu.get_register(4));
+}
+
+#[test]
+fn op_fx0a_press_quirk_test() {
+    let cpu = do_fx0a(true, "2 down 7", 3);
+    assert_eq!(CpuState::Running, cpu.get_state());
+    assert_eq!(7, cpu.get_register(4));
+
+    // a key already held when FX0A starts waiting doesn't count
+    let cpu = do_fx0a(true, "0 down 7", 10);
+    assert_eq!(CpuState::WaitingForKey { register: 4, key: None }, cpu.get_state());
+}
+
+#[test]
+fn unknown_opcode_test() {
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x2A4;
+    cpu.opcode = 0xF1FF;
+
+    match cpu.execute_opcode() {
+        Err(EmulatorError::UnknownOpcode { pc, opcode }) => {
+            assert_eq!(0x2A4, pc);
+            assert_eq!(0xF1FF, opcode);
+        },
+        other => panic!("expected an unknown opcode error, got {:?}", other),
+    };
+    assert_eq!("unknown opcode 0xF1FF at 0x2A4", cpu.execute_opcode().unwrap_err().to_string());
+}
+
+#[test]
+fn fetch_at_end_of_memory_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.memory[0xFFF] = 0x6A;
+    cpu.memory[0x000] = 0x42;
+    cpu.pc = 0xFFF;
+
+    // by default the fetch wraps around to 0x000
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(0x6A42, cpu.opcode);
+    assert_eq!(0x42, cpu.get_register(0xA));
+
+    cpu.set_memory_policy(MemoryPolicy::Fault);
+    cpu.pc = 0xFFF;
+    match cpu.emulate_cycle() {
+        Err(EmulatorError::MemoryOutOfBounds { addr }) => assert_eq!(0x1000, addr),
+        other => panic!("expected an out of bounds error, got {:?}", other),
+    };
+}
+
+#[test]
+fn op_fx55_out_of_bounds_test() {
+    let mut cpu = Cpu::new();
+    cpu.set_register(0x0, 0x11);
+    cpu.set_register(0x1, 0x22);
+    cpu.i = 0xFFF;
+
+    cpu.set_memory_policy(MemoryPolicy::Log);
+    cpu.opcode = 0xF155;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x11, cpu.memory[0xFFF]);
+    assert_eq!(0x00, cpu.memory[0x000]);
+
+    cpu.set_memory_policy(MemoryPolicy::Wrap);
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x22, cpu.memory[0x000]);
+}
+
+#[test]
+fn load_rom_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+
+    cpu.load_rom(&[0xAB; 4096 - PROGRAM_START]).unwrap();
+    assert_eq!(0xAB, cpu.memory[0xFFF]);
+
+    match cpu.load_rom(&[0xCD; 4096 - PROGRAM_START + 1]) {
+        Err(EmulatorError::RomTooLarge { size, max }) => {
+            assert_eq!(3585, size);
+            assert_eq!(3584, max);
+        },
+        other => panic!("expected a ROM too large error, got {:?}", other),
+    };
+    // nothing was written
+    assert_eq!(0xAB, cpu.memory[PROGRAM_START]);
+}
+
+#[test]
+fn load_address_test() {
+    let mut cpu = Cpu::new();
+    cpu.set_load_address(ETI_660_PROGRAM_START);
+    cpu.initialize();
+    assert_eq!(ETI_660_PROGRAM_START, cpu.pc);
+
+    cpu.load_rom(&[0x6A, 0x42]).unwrap();
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(0x42, cpu.get_register(0xA));
+    assert!(cpu.load_rom(&[0; 4096 - ETI_660_PROGRAM_START + 1]).is_err());
+}
+
+#[test]
+fn shift_quirk_test() {
+    let mut cpu = Cpu::new();
+    cpu.vp[0x1] = 0x81;
+    cpu.vp[0x2] = 0x03;
+
+    // modern : v[1] is shifted in place
+    cpu.opcode = 0x812E;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x02, cpu.vp[0x1]);
+    assert_eq!(1, cpu.vp[0xF]);
+
+    // cosmac vip : v[2] is shifted into v[1]
+    cpu.set_quirks(Quirks::cosmac_vip());
+    cpu.opcode = 0x8126;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x01, cpu.vp[0x1]);
+    assert_eq!(1, cpu.vp[0xF]);
+
+    // the flag wins when the result goes to v[F]
+    cpu.vp[0xF] = 0x80;
+    cpu.opcode = 0x8FFE;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(1, cpu.vp[0xF]);
+}
+
+#[test]
+fn logic_vf_reset_quirk_test() {
+    let mut cpu = Cpu::new();
+    cpu.vp[0xF] = 1;
+
+    cpu.opcode = 0x8011;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(1, cpu.vp[0xF]);
+
+    cpu.set_quirks(Quirks::cosmac_vip());
+    cpu.opcode = 0x8012;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0, cpu.vp[0xF]);
+}
+
+#[test]
+fn jump_quirk_test() {
+    let mut cpu = Cpu::new();
+    cpu.vp[0x0] = 0x10;
+    cpu.vp[0x3] = 0x20;
+
+    cpu.opcode = 0xB300;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x310 - 2, cpu.pc);
+
+    cpu.set_quirks(Quirks::chip_48());
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x320 - 2, cpu.pc);
+}
+
+#[test]
+fn load_store_quirk_test() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+
+    cpu.opcode = 0xF255;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x300, cpu.i);
+
+    cpu.set_quirks(Quirks::cosmac_vip());
+    cpu.opcode = 0xF265;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(0x303, cpu.i);
+}
+
+#[test]
+fn sprite_clip_wrap_test() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.get_memory_mut()[0x300] = 0xFF;
+    cpu.get_memory_mut()[0x301] = 0xFF;
+    cpu.vp[0x0] = 60;
+    cpu.vp[0x1] = 31;
+
+    // modern : the sprite wraps on the same row and to the top of the screen
+    cpu.opcode = 0xD012;
+    cpu.execute_opcode().unwrap();
+    let display = cpu.get_display();
+    assert_eq!(1, display[31 * 64 + 63]);
+    assert_eq!(1, display[31 * 64 + 3]);
+    assert_eq!(0, display[31 * 64 + 4]);
+    assert_eq!(1, display[2]);
+    assert_eq!(0, display[64 + 2]);
+
+    // clipped : only the part on the screen is drawn and the cpu waits for the next frame
+    let mut cpu2 = Cpu::new();
+    cpu2.set_quirks(Quirks::cosmac_vip());
+    cpu2.i = 0x300;
+    cpu2.get_memory_mut()[0x300] = 0xFF;
+    cpu2.get_memory_mut()[0x301] = 0xFF;
+    cpu2.vp[0x0] = 60 + 64;
+    cpu2.vp[0x1] = 31;
+    cpu2.opcode = 0xD012;
+    cpu2.execute_opcode().unwrap();
+    let display = cpu2.get_display();
+    assert_eq!(4, display.iter().filter(|pixel| **pixel == 1).count());
+    assert_eq!(1, display[31 * 64 + 60]);
+    assert_eq!(CpuState::WaitingForVBlank, cpu2.get_state());
+
+    cpu2.tick_timers();
+    assert_eq!(CpuState::Running, cpu2.get_state());
+}
+
+#[test]
+fn schip_hires_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+
+    cpu.opcode = 0x00FF;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(128 * 64, cpu.get_display().len());
+
+    // 16x16 sprite at the bottom right corner, wrapped
+    cpu.i = 0x300;
+    for index in 0..32 {
+        cpu.get_memory_mut()[0x300 + index] = 0xFF;
+    }
+    cpu.vp[0x0] = 120;
+    cpu.vp[0x1] = 56;
+    cpu.opcode = 0xD010;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(256, cpu.get_display().iter().filter(|pixel| **pixel == 1).count());
+    assert_eq!(0, cpu.vp[0xF]);
+
+    // super-chip counts the rows that collided
+    cpu.set_quirks(Quirks::super_chip());
+    cpu.vp[0x1] = 60;
+    cpu.opcode = 0xD012;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(2, cpu.vp[0xF]);
+
+    cpu.opcode = 0x00FE;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(64 * 32, cpu.get_display().len());
+    assert!(cpu.get_display().iter().all(|pixel| *pixel == 0));
+}
+
+#[test]
+fn schip_scroll_test() {
+    let mut cpu = Cpu::new();
+    let mut display = [0u8; 64 * 32];
+    display[0] = 1;
+    cpu.set_display(&display);
+
+    cpu.opcode = 0x00C3;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0x00FB;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(1, cpu.get_display()[3 * 64 + 4]);
+    assert_eq!(1, cpu.get_display().iter().filter(|pixel| **pixel == 1).count());
+
+    cpu.opcode = 0x00FC;
+    cpu.execute_opcode().unwrap();
+    cpu.execute_opcode().unwrap();
+    assert!(cpu.get_display().iter().all(|pixel| *pixel == 0));
+}
+
+#[test]
+fn schip_font_flags_exit_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+
+    cpu.vp[0x2] = 9;
+    cpu.opcode = 0xF230;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(crate::fontset::BIG_FONTSET_LOCATION + 90, cpu.i);
+    assert_eq!(0x3C, cpu.get_memory()[cpu.i]);
+
+    cpu.vp[0x0] = 7;
+    cpu.vp[0x1] = 8;
+    cpu.opcode = 0xF175;
+    cpu.execute_opcode().unwrap();
+    cpu.vp[0x0] = 0;
+    cpu.vp[0x1] = 0;
+    cpu.opcode = 0xF185;
+    cpu.execute_opcode().unwrap();
+    assert_eq!([7, 8], cpu.vp[..2]);
+
+    cpu.get_memory_mut().write_u16(0x200, 0x00FD).unwrap();
+    assert_eq!(StepOutcome::Exit, cpu.emulate_cycle().unwrap());
+    assert_eq!(StepOutcome::Exit, cpu.emulate_cycle().unwrap());
+    assert_eq!(0x202, cpu.pc);
+}
+
+#[test]
+fn xo_chip_long_load_and_skip_test() {
+    let mut cpu = Cpu::new();
+    cpu.set_memory_size(crate::memory::XO_CHIP_MEMORY_SIZE);
+    cpu.initialize();
+    cpu.load_rom(&[
+        0xF0, 0x00, 0xAB, 0xCD, // I = 0xABCD
+        0x30, 0x00,             // v[0] == 0, skip the whole long load
+        0xF0, 0x00, 0x12, 0x34,
+        0x60, 0x01,
+    ]).unwrap();
+
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(0xABCD, cpu.i);
+    assert_eq!(0x204, cpu.pc);
+
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(0x20A, cpu.pc);
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(0xABCD, cpu.i);
+    assert_eq!(1, cpu.vp[0x0]);
+}
+
+#[test]
+fn xo_chip_register_range_test() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.vp[0x2] = 2;
+    cpu.vp[0x3] = 3;
+    cpu.vp[0x4] = 4;
+
+    cpu.opcode = 0x5242;
+    cpu.execute_opcode().unwrap();
+    assert_eq!([2, 3, 4], cpu.get_memory().as_slice()[0x300..0x303]);
+    assert_eq!(0x300, cpu.i);
+
+    // reversed order
+    cpu.opcode = 0x5863;
+    cpu.execute_opcode().unwrap();
+    assert_eq!([4, 3, 2], cpu.vp[6..9]);
+}
+
+#[test]
+fn xo_chip_planes_test() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.get_memory_mut()[0x300] = 0x80;
+    cpu.get_memory_mut()[0x301] = 0xC0;
+
+    // both planes : the first byte goes to plane 1, the second to plane 2
+    cpu.opcode = 0xF301;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0xD011;
+    cpu.execute_opcode().unwrap();
+    assert_eq!([3, 2], cpu.get_display()[..2]);
+
+    // clearing only the second plane
+    cpu.opcode = 0xF201;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0x00E0;
+    cpu.execute_opcode().unwrap();
+    assert_eq!([1, 0], cpu.get_display()[..2]);
+
+    // scrolling only the first plane
+    cpu.opcode = 0xF101;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0x00C1;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0x00D1;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(1, cpu.get_display()[0]);
+    assert_eq!(1, cpu.get_display().iter().filter(|pixel| **pixel != 0).count());
+}
+
+#[test]
+fn xo_chip_audio_test() {
+    let mut cpu = Cpu::new();
+    assert_eq!(None, cpu.get_audio_pattern());
+
+    cpu.i = 0x300;
+    cpu.get_memory_mut()[0x300] = 0xF0;
+    cpu.vp[0x1] = 112;
+    cpu.opcode = 0xF002;
+    cpu.execute_opcode().unwrap();
+    cpu.opcode = 0xF13A;
+    cpu.execute_opcode().unwrap();
+
+    let pattern = cpu.get_audio_pattern().unwrap();
+    assert_eq!(0xF0, pattern.bits[0]);
+    assert_eq!(112, pattern.pitch);
+    assert_eq!(8000.0, pattern.rate());
+}
+
+#[test]
+fn save_state_test() {
+    let mut cpu = Cpu::new();
+    cpu.set_stack_config(StackConfig::cosmac_vip()).unwrap();
+    cpu.initialize();
+    cpu.load_rom(&[0x60, 0x05, 0xA0, 0x80, 0x22, 0x08, 0x12, 0x06, 0xD0, 0x05, 0x00, 0xEE]).unwrap();
+    for _ in 0..4 {
+        cpu.emulate_cycle().unwrap();
+    }
+    cpu.keypad.set_key(0x4, true);
+    let state = cpu.save_state();
+
+    // diverge, then come back
+    cpu.emulate_cycle().unwrap();
+    cpu.emulate_cycle().unwrap();
+    cpu.vp[0x3] = 9;
+    cpu.load_state(&state).unwrap();
+    assert_eq!(5, cpu.vp[0x0]);
+    assert_eq!(0, cpu.vp[0x3]);
+    assert_eq!(0x20A, cpu.pc);
+    assert_eq!(vec![0x204], cpu.get_stack());
+    assert!(cpu.get_display().contains(&1));
+    assert!(cpu.get_keypad().is_key_pressed(0x4));
+    assert_eq!(state, cpu.save_state());
+
+    // another ROM
+    let mut other = Cpu::new();
+    other.load_rom(&[0x00, 0xE0]).unwrap();
+    assert!(matches!(other.load_state(&state), Err(EmulatorError::IncompatibleSaveState(_))));
+
+    // another profile
+    cpu.set_quirks(Quirks::cosmac_vip());
+    assert!(matches!(cpu.load_state(&state), Err(EmulatorError::IncompatibleSaveState(_))));
+    cpu.set_quirks(Quirks::modern());
+
+    // truncated files leave the cpu as it was
+    cpu.vp[0x3] = 9;
+    assert!(matches!(cpu.load_state(&state[..state.len() - 1]), Err(EmulatorError::InvalidSaveState(_))));
+    assert_eq!(9, cpu.vp[0x3]);
+}
+
+#[test]
+fn trace_test() {
+    let path = std::env::temp_dir().join(format!("chip8_trace_test_{}.log", std::process::id()));
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&[0x60, 0x05, 0x70, 0x01, 0x70, 0x02, 0x12, 0x06]).unwrap();
+    let filter = TraceFilter { classes: 1 << 0x7, pc_range: (0x200, 0x203) };
+    cpu.set_tracer(Some(Tracer::new(filter, Box::new(File::create(&path).unwrap()), false)));
+
+    for _ in 0..4 {
+        cpu.emulate_cycle().unwrap();
+    }
+    // dropping the tracer closes the file
+    cpu.set_tracer(None);
+
+    let trace = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    // only 7XNN at 0x202 is in the class and the range, with V0 as it was before the add
+    assert_eq!("PC=0202 OP=7001 I=0000 SP=0 DT=00 ST=00 V=05000000000000000000000000000000\n", trace);
+}
@@ -0,0 +1,346 @@
+use crate::breakpoint::{parse_hex, Access, Breakpoint, Condition, Register};
+use crate::cpu::Cpu;
+use crate::error::{EmulatorError, StepOutcome};
+use crate::instruction;
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+use crate::scheduler::TIMER_HZ;
+
+use std::fmt::Write;
+
+// bytes shown by `dump` when no length is given
+const DEFAULT_DUMP_LENGTH: usize = 64;
+// instructions shown by `list` when no count is given
+const DEFAULT_LIST_COUNT: usize = 10;
+// instructions listed before the pc so the context is visible
+const LIST_CONTEXT: usize = 3;
+// instructions `continue` runs at most when no count is given, there is no other way to stop it
+const DEFAULT_CONTINUE_LIMIT: usize = 0x100000;
+
+pub const DEBUGGER_HELP: &str = "\
+Numbers are in hex, with or without 0x, except for the breakpoint ids.
+    s, step [N]             run N instructions (default 1)
+    c, continue [N]         run until a breakpoint, the end of the program or a key wait,
+                            at most N instructions (default 100000)
+    b, break <ADDR>         stop before the instruction at ADDR
+    b, break op <OPCODE>    stop before the opcodes of a class, X Y and N match any digit like DXYN or FX0A
+    b, break if <COND>      stop before any instruction when COND holds, like V3 == 10 && I > 300
+                            (==, !=, <, <=, >, >=, && and || on V0-VF, I, PC, SP, DT, ST and numbers)
+    w, watch <ADDR>[-<END>] [r|w|rw]
+                            stop after an instruction reads or writes memory in the range (default w)
+    w, watch <REG>          stop after an instruction changes V0-VF, I, SP, DT or ST
+    b, break                list the breakpoints and watchpoints with their ids
+    d, delete <ID>          remove a breakpoint or watchpoint, the ID is in decimal
+    r, regs                 print the registers, I, PC, SP and the timers
+    set <REG> <VALUE>       change V0-VF, I, PC, SP, DT or ST
+    x, dump <ADDR> [LEN]    hex dump LEN bytes of memory (default 40)
+    poke <ADDR> <BYTE>...   write bytes to memory starting at ADDR
+    k, key [KEY]...         hold down these keys of the keypad and release the others, none releases them all
+    bt, stack               print the call stack
+    l, list [ADDR] [N]      disassemble N instructions around ADDR (default the pc)
+    h, help                 print this help
+    q, quit                 stop debugging";
+
+/// A parsed debugger command
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugCommand {
+    Step(usize),
+    /// runs at most this many instructions
+    Continue(usize),
+    /// adds a breakpoint or a watchpoint, None lists them
+    Break(Option<Breakpoint>),
+    Delete(usize),
+    Registers,
+    Set(Register, usize),
+    Dump { addr: usize, length: usize },
+    Poke { addr: usize, bytes: Vec<u8> },
+    /// the keys held from now on
+    Keys(Vec<u8>),
+    Stack,
+    List { addr: Option<usize>, count: usize },
+    Help,
+    Quit,
+}
+
+fn parse_watch(args: &[&str]) -> Result<Breakpoint, String> {
+    let target = args.first().ok_or_else(|| String::from("watch needs an address or a register"))?;
+    if let Ok(register) = target.parse::<Register>() {
+        if register == Register::Pc {
+            return Err(String::from("the pc changes on every instruction, use break instead"));
+        }
+        return Ok(Breakpoint::Register(register));
+    }
+
+    let (start, end) = match target.split_once('-') {
+        Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+        None => (parse_hex(target)?, parse_hex(target)?),
+    };
+    if start > end {
+        return Err(format!("the range {} ends before it starts", target));
+    }
+    let access = match args.get(1).copied() {
+        None | Some("w") => Access::Write,
+        Some("r") => Access::Read,
+        Some("rw") => Access::ReadWrite,
+        Some(other) => return Err(format!("unknown access '{}', expected r, w or rw", other)),
+    };
+
+    Ok(Breakpoint::Memory { start, end, access })
+}
+
+fn parse_break(args: &[&str]) -> Result<Option<Breakpoint>, String> {
+    match args {
+        [] => Ok(None),
+        ["op", opcode] => Breakpoint::opcode(opcode).map(Some),
+        ["if", condition @ ..] if !condition.is_empty() => Ok(Some(Breakpoint::Condition(condition.join(" ").parse::<Condition>()?))),
+        [addr] => Ok(Some(Breakpoint::Address(parse_hex(addr)?))),
+        _ => Err(String::from("expected break <ADDR>, break op <OPCODE> or break if <CONDITION>")),
+    }
+}
+
+/// Parses a line typed at the debugger prompt, an empty line is an error the caller can ignore
+pub fn parse_command(line: &str) -> Result<DebugCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return Err(String::from("empty command")),
+    };
+    let arg = |index: usize| args.get(index).map(|arg| parse_hex(arg)).transpose();
+    let required = |index: usize, what: &str| arg(index)?.ok_or_else(|| format!("{} needs {}", name, what));
+    // no memory is larger, the sums with an offset can't overflow below it
+    let in_memory = |addr: usize| match addr < XO_CHIP_MEMORY_SIZE {
+        true => Ok(addr),
+        false => Err(format!("0x{:X} is past the end of the memory", addr)),
+    };
+
+    let command = match name {
+        "s" | "step" => DebugCommand::Step(arg(0)?.unwrap_or(1)),
+        "c" | "continue" => DebugCommand::Continue(arg(0)?.unwrap_or(DEFAULT_CONTINUE_LIMIT)),
+        "b" | "break" => DebugCommand::Break(parse_break(args)?),
+        "w" | "watch" => DebugCommand::Break(Some(parse_watch(args)?)),
+        "d" | "delete" => {
+            let id = args.first().ok_or_else(|| String::from("delete needs a breakpoint id"))?;
+            DebugCommand::Delete(id.parse().map_err(|_| format!("invalid breakpoint id '{}'", id))?)
+        },
+        "r" | "regs" => DebugCommand::Registers,
+        "set" => {
+            let register = args.first().ok_or_else(|| String::from("set needs a register"))?.parse::<Register>()?;
+            let value = required(1, "a value")?;
+            match register {
+                Register::I | Register::Pc => DebugCommand::Set(register, in_memory(value)?),
+                _ => DebugCommand::Set(register, value),
+            }
+        },
+        "x" | "dump" => DebugCommand::Dump { addr: required(0, "an address")?, length: arg(1)?.unwrap_or(DEFAULT_DUMP_LENGTH) },
+        "poke" => {
+            let addr = in_memory(required(0, "an address")?)?;
+            let bytes = args[1..].iter().map(|arg| match parse_hex(arg)? {
+                byte if byte <= 0xFF => Ok(byte as u8),
+                _ => Err(format!("{} doesn't fit in a byte", arg)),
+            }).collect::<Result<Vec<u8>, String>>()?;
+            if bytes.is_empty() {
+                return Err(String::from("poke needs at least one byte"));
+            }
+            DebugCommand::Poke { addr, bytes }
+        },
+        "k" | "key" => DebugCommand::Keys(args.iter().map(|arg| match parse_hex(arg)? {
+            key if key <= 0xF => Ok(key as u8),
+            _ => Err(format!("key {} must be between 0 and F", arg)),
+        }).collect::<Result<Vec<u8>, String>>()?),
+        "bt" | "stack" => DebugCommand::Stack,
+        "l" | "list" => DebugCommand::List { addr: arg(0)?, count: arg(1)?.unwrap_or(DEFAULT_LIST_COUNT) },
+        "h" | "help" => DebugCommand::Help,
+        "q" | "quit" => DebugCommand::Quit,
+        _ => return Err(format!("unknown command '{}', type help for the list", name)),
+    };
+
+    Ok(command)
+}
+
+/// Runs the CPU instruction by instruction for the debugger prompt, the breakpoints are kept by the CPU.
+/// The timers tick every instructions_per_second / 60 instructions so games waiting on them behave as they would in real time
+pub struct Debugger {
+    instructions_per_tick: usize,
+    // instructions run since the last timer tick
+    cycles: usize,
+}
+
+impl Debugger {
+    pub fn new(instructions_per_second: u32) -> Debugger {
+        Debugger {
+            instructions_per_tick: (instructions_per_second / TIMER_HZ).max(1) as usize,
+            cycles: 0,
+        }
+    }
+
+    /// Runs one instruction and ticks the timers when their turn comes
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<StepOutcome, EmulatorError> {
+        let outcome = cpu.emulate_cycle()?;
+
+        self.cycles += 1;
+        if self.cycles >= self.instructions_per_tick {
+            self.cycles = 0;
+            cpu.tick_timers();
+        }
+
+        Ok(outcome)
+    }
+
+    /// Runs one instruction, Err holds the reason to stop
+    fn step_or_stop(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        let pc = cpu.get_pc();
+        match self.step(cpu).map_err(|err| format!("error at 0x{:03X} : {}", pc, err))? {
+            StepOutcome::Break(reason) => Err(reason.to_string()),
+            StepOutcome::Exit => Err(String::from("the program exited")),
+            StepOutcome::WaitingForKey => Err(String::from("waiting for a key")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a command and returns what to print
+    pub fn execute(&mut self, cpu: &mut Cpu, command: DebugCommand) -> String {
+        let mut out = String::new();
+
+        match command {
+            DebugCommand::Step(count) => {
+                cpu.resume();
+                for _ in 0..count {
+                    if let Err(reason) = self.step_or_stop(cpu) {
+                        let _ = writeln!(out, "{}", reason);
+                        break;
+                    }
+                }
+                out.push_str(&format_list(cpu, cpu.get_pc(), 1));
+            },
+            DebugCommand::Continue(limit) => {
+                // the instruction under a breakpoint we are stopped on runs first
+                cpu.resume();
+                let stop = (0..limit).find_map(|_| self.step_or_stop(cpu).err())
+                    .unwrap_or_else(|| format!("nothing stopped the program in 0x{:X} instructions", limit));
+                let _ = writeln!(out, "{}", stop);
+                out.push_str(&format_list(cpu, cpu.get_pc(), 1));
+            },
+            DebugCommand::Break(Some(breakpoint)) => {
+                let text = breakpoint.to_string();
+                let id = cpu.get_breakpoints_mut().add(breakpoint);
+                let _ = writeln!(out, "#{} {}", id, text);
+            },
+            DebugCommand::Break(None) if cpu.get_breakpoints().is_empty() => out.push_str("no breakpoints\n"),
+            DebugCommand::Break(None) => cpu.get_breakpoints().iter().for_each(|(id, breakpoint)| { let _ = writeln!(out, "#{} {}", id, breakpoint); }),
+            DebugCommand::Delete(id) => {
+                if !cpu.get_breakpoints_mut().remove(id) {
+                    let _ = writeln!(out, "no breakpoint #{}", id);
+                }
+            },
+            DebugCommand::Registers => out.push_str(&format_registers(cpu)),
+            DebugCommand::Set(register, value) => {
+                if let Err(reason) = register.write(cpu, value) {
+                    let _ = writeln!(out, "{}", reason);
+                }
+            },
+            DebugCommand::Dump { addr, length } => out.push_str(&format_dump(cpu, addr, length)),
+            DebugCommand::Poke { addr, bytes } => {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    if let Err(err) = cpu.get_memory_mut().write(addr + offset, *byte) {
+                        let _ = writeln!(out, "{}", err);
+                        break;
+                    }
+                }
+            },
+            DebugCommand::Keys(keys) => {
+                let mut held = [false; 16];
+                keys.iter().for_each(|key| held[*key as usize] = true);
+                // latched like a frame of the input source, FX0A sees the keys that went down or up
+                cpu.get_keypad().set_keys(held);
+                let _ = match keys.is_empty() {
+                    true => writeln!(out, "no key held"),
+                    false => writeln!(out, "holding {}", keys.iter().map(|key| format!("{:X}", key)).collect::<Vec<String>>().join(" ")),
+                };
+            },
+            DebugCommand::Stack => out.push_str(&format_stack(cpu)),
+            DebugCommand::List { addr, count } => {
+                // a few instructions before the pc give some context, an explicit address starts there
+                let start = addr.unwrap_or_else(|| cpu.get_pc().saturating_sub(2 * LIST_CONTEXT));
+                out.push_str(&format_list(cpu, start, count));
+            },
+            DebugCommand::Help => {
+                out.push_str(DEBUGGER_HELP);
+                out.push('\n');
+            },
+            DebugCommand::Quit => (),
+        };
+
+        out
+    }
+}
+
+/// `count` instructions decoded from `start`, `=>` marks the pc and `*` the breakpoints
+pub fn format_list(cpu: &Cpu, start: usize, count: usize) -> String {
+    let memory = cpu.get_memory().as_slice();
+    let mut out = String::new();
+    let mut addr = start;
+
+    for _ in 0..count {
+        if addr >= memory.len() {
+            break;
+        }
+        let marker = if addr == cpu.get_pc() { "=>" } else { "  " };
+        let breakpoint = if cpu.get_breakpoints().iter().any(|(_, breakpoint)| *breakpoint == Breakpoint::Address(addr)) { '*' } else { ' ' };
+        let (text, size) = match instruction::decode(&memory[addr..]) {
+            Some(instruction) => (instruction.to_string(), instruction.size()),
+            None => (format!("DB 0x{:02X}, 0x{:02X}", memory[addr], memory.get(addr + 1).copied().unwrap_or(0)), 2),
+        };
+        let bytes: Vec<String> = memory[addr..(addr + size).min(memory.len())].iter().map(|byte| format!("{:02X}", byte)).collect();
+        let _ = writeln!(out, "{}{} {:03X}  {:<12} {}", marker, breakpoint, addr, bytes.join(" "), text);
+        addr += size;
+    }
+
+    out
+}
+
+fn format_registers(cpu: &Cpu) -> String {
+    let mut out = format!("PC={:03X} I={:03X} SP={:X} DT={:02X} ST={:02X}\n", cpu.get_pc(), cpu.get_i(), cpu.get_sp(), cpu.get_delay_timer(), cpu.get_sound_timer());
+    for row in 0..2 {
+        let registers: Vec<String> = (row * 8..row * 8 + 8).map(|index| format!("V{:X}={:02X}", index, cpu.get_register(index))).collect();
+        let _ = writeln!(out, "{}", registers.join(" "));
+    }
+
+    out
+}
+
+/// 16 bytes per line with their ASCII, stops at the end of the memory
+fn format_dump(cpu: &Cpu, addr: usize, length: usize) -> String {
+    let memory = cpu.get_memory().as_slice();
+    let end = addr.saturating_add(length).min(memory.len());
+    let mut out = String::new();
+
+    for line in (addr..end).step_by(16) {
+        let bytes = &memory[line..(line + 16).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let ascii: String = bytes.iter().map(|byte| if byte.is_ascii_graphic() { *byte as char } else { '.' }).collect();
+        let _ = writeln!(out, "{:03X}  {:<47}  {}", line, hex.join(" "), ascii);
+    }
+    if addr >= memory.len() {
+        let _ = writeln!(out, "0x{:X} is past the end of the memory", addr);
+    }
+
+    out
+}
+
+/// Addresses of the calls on the stack, from the innermost to the outermost
+fn format_stack(cpu: &Cpu) -> String {
+    let stack = cpu.get_stack();
+    if stack.is_empty() {
+        return String::from("the stack is empty\n");
+    }
+
+    let mut out = String::new();
+    for (depth, addr) in stack.iter().rev().enumerate() {
+        let _ = writeln!(out, "#{} called from 0x{:03X}", depth, addr);
+    }
+
+    out
+}
+
+#[cfg(test)]
+#[path = "debugger_test.rs"]
+mod debugger_test;
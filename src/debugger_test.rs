@@ -0,0 +1,161 @@
+use super::*;
+
+use crate::quirks::Quirks;
+
+// V0 = 5, calls 0x208 which adds 1 to V0 and returns, then loops on itself
+const ROM: [u8; 12] = [0x60, 0x05, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&ROM).unwrap();
+    cpu
+}
+
+#[test]
+fn parse_command_test() {
+    assert_eq!(Ok(DebugCommand::Step(1)), parse_command("s"));
+    assert_eq!(Ok(DebugCommand::Step(0x10)), parse_command("step 10"));
+    assert_eq!(Ok(DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)), parse_command("c"));
+    assert_eq!(Ok(DebugCommand::Continue(0x500)), parse_command("continue 500"));
+    assert_eq!(Ok(DebugCommand::Keys(vec![0x5, 0xA])), parse_command("key 5 a"));
+    assert_eq!(Ok(DebugCommand::Keys(Vec::new())), parse_command("k"));
+    assert_eq!(Ok(DebugCommand::Break(Some(Breakpoint::Address(0x208)))), parse_command("b 0x208"));
+    assert_eq!(Ok(DebugCommand::Break(Some(Breakpoint::Opcode { pattern: 0xF00A, mask: 0xF0FF, name: String::from("FX0A") }))), parse_command("break op FX0A"));
+    assert_eq!(Ok(DebugCommand::Break(Some(Breakpoint::Memory { start: 0x300, end: 0x30F, access: Access::ReadWrite }))), parse_command("w 300-30F rw"));
+    assert_eq!(Ok(DebugCommand::Break(Some(Breakpoint::Memory { start: 0x300, end: 0x300, access: Access::Write }))), parse_command("watch 300"));
+    assert_eq!(Ok(DebugCommand::Break(Some(Breakpoint::Register(Register::V(3))))), parse_command("w v3"));
+    assert_eq!(Ok(DebugCommand::Break(None)), parse_command("b"));
+    assert_eq!(Ok(DebugCommand::Delete(12)), parse_command("d 12"));
+    assert_eq!("#1 break if V3 == 10 && I > 300", match parse_command("b if V3 == 10  &&I>300").unwrap() {
+        DebugCommand::Break(Some(breakpoint)) => format!("#1 {}", breakpoint),
+        other => panic!("expected a breakpoint, got {:?}", other),
+    });
+    assert_eq!(Ok(DebugCommand::Set(Register::V(0xA), 0xFF)), parse_command("set va FF"));
+    assert_eq!(Ok(DebugCommand::Set(Register::DelayTimer, 0x3C)), parse_command("set DT 3C"));
+    assert_eq!(Ok(DebugCommand::Dump { addr: 0x200, length: 64 }), parse_command("x 200"));
+    assert_eq!(Ok(DebugCommand::Poke { addr: 0x300, bytes: vec![0x12, 0x00] }), parse_command("poke 300 12 0"));
+    assert_eq!(Ok(DebugCommand::List { addr: None, count: 10 }), parse_command(" l "));
+
+    assert_eq!("empty command", parse_command("  ").unwrap_err());
+    assert_eq!("unknown command 'jump', type help for the list", parse_command("jump 200").unwrap_err());
+    assert_eq!("invalid number 'zz'", parse_command("b zz").unwrap_err());
+    assert_eq!("set needs a value", parse_command("set v1").unwrap_err());
+    assert_eq!("unknown register 'V10', expected V0-VF, I, PC, SP, DT or ST", parse_command("set V10 1").unwrap_err());
+    assert_eq!("100 doesn't fit in a byte", parse_command("poke 200 100").unwrap_err());
+    assert_eq!("0xFFFFFFFFFFFFFFFF is past the end of the memory", parse_command("poke FFFFFFFFFFFFFFFF 1").unwrap_err());
+    assert_eq!("0x10000 is past the end of the memory", parse_command("set I 10000").unwrap_err());
+    assert_eq!(Ok(DebugCommand::Set(Register::I, 0xFFFF)), parse_command("set i FFFF"));
+    assert_eq!("key 10 must be between 0 and F", parse_command("key 10").unwrap_err());
+    assert_eq!("the pc changes on every instruction, use break instead", parse_command("w pc").unwrap_err());
+    assert_eq!("unknown access 'x', expected r, w or rw", parse_command("w 300 x").unwrap_err());
+    assert_eq!("invalid breakpoint id 'A'", parse_command("d A").unwrap_err());
+    assert_eq!("expected a comparison in 'V3'", parse_command("b if V3").unwrap_err());
+}
+
+#[test]
+fn step_and_continue_test() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new(600);
+
+    assert_eq!("=>  20A  00 EE        RET\n", debugger.execute(&mut cpu, DebugCommand::Step(3)));
+    assert_eq!(6, cpu.get_register(0));
+    assert_eq!("#0 called from 0x202\n", debugger.execute(&mut cpu, DebugCommand::Stack));
+
+    // the breakpoint stops the loop on its second turn since continue first runs the instruction under the pc
+    assert_eq!("#1 break at 0x204\n", debugger.execute(&mut cpu, DebugCommand::Break(Some(Breakpoint::Address(0x204)))));
+    assert_eq!("#1 stopped at 0x204\n=>* 204  12 04        JP 0x204\n", debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)));
+    assert_eq!("#1 stopped at 0x204\n=>* 204  12 04        JP 0x204\n", debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)));
+    assert_eq!("the stack is empty\n", debugger.execute(&mut cpu, DebugCommand::Stack));
+
+    assert_eq!("#1 break at 0x204\n", debugger.execute(&mut cpu, DebugCommand::Break(None)));
+    assert_eq!("", debugger.execute(&mut cpu, DebugCommand::Delete(1)));
+    assert_eq!("no breakpoints\n", debugger.execute(&mut cpu, DebugCommand::Break(None)));
+    assert_eq!("no breakpoint #1\n", debugger.execute(&mut cpu, DebugCommand::Delete(1)));
+}
+
+#[test]
+fn watch_test() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new(600);
+
+    // V0 is set to 5, then goes to 6 in the subroutine
+    debugger.execute(&mut cpu, parse_command("w v0").unwrap());
+    assert_eq!("#1 0x200 changed V0 from 0x00 to 0x05\n=>  202  22 08        CALL 0x208\n", debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)));
+    assert_eq!("#1 0x208 changed V0 from 0x05 to 0x06\n=>  20A  00 EE        RET\n", debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)));
+    assert_eq!("=>  204  12 04        JP 0x204\n", debugger.execute(&mut cpu, DebugCommand::Step(1)));
+
+    // continuing runs the jump under the pc before the opcode breakpoint can stop on it
+    assert_eq!("#2 break on 1NNN\n", debugger.execute(&mut cpu, parse_command("b op 1nnn").unwrap()));
+    assert_eq!("#2 stopped at 0x204\n=>  204  12 04        JP 0x204\n", debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)));
+}
+
+#[test]
+fn timers_test() {
+    let mut cpu = cpu();
+    cpu.set_delay_timer(2);
+    // 120 instructions per second is 2 instructions per tick
+    let mut debugger = Debugger::new(120);
+
+    debugger.execute(&mut cpu, DebugCommand::Step(3));
+    assert_eq!(1, cpu.get_delay_timer());
+    debugger.execute(&mut cpu, DebugCommand::Step(1));
+    assert_eq!(0, cpu.get_delay_timer());
+}
+
+#[test]
+fn stop_reasons_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&[0x00, 0xFD, 0xFF, 0xFF]).unwrap();
+    let mut debugger = Debugger::new(600);
+    assert!(debugger.execute(&mut cpu, DebugCommand::Continue(DEFAULT_CONTINUE_LIMIT)).starts_with("the program exited\n"));
+
+    cpu.initialize();
+    cpu.load_rom(&[0xFF, 0xFF]).unwrap();
+    assert!(debugger.execute(&mut cpu, DebugCommand::Step(2)).starts_with("error at 0x200 : unknown opcode 0xFFFF at 0x200\n"));
+}
+
+#[test]
+fn continue_limit_and_keys_test() {
+    // waits for a key in V1 then loops on itself
+    let mut cpu = Cpu::new();
+    cpu.set_quirks(Quirks::super_chip());
+    cpu.initialize();
+    cpu.load_rom(&[0xF1, 0x0A, 0x12, 0x02]).unwrap();
+    let mut debugger = Debugger::new(600);
+
+    assert_eq!("waiting for a key\n=>  202  12 02        JP 0x202\n", debugger.execute(&mut cpu, parse_command("c").unwrap()));
+    assert_eq!("holding 7\n", debugger.execute(&mut cpu, parse_command("key 7").unwrap()));
+    assert_eq!("nothing stopped the program in 0x100 instructions\n=>  202  12 02        JP 0x202\n", debugger.execute(&mut cpu, parse_command("c 100").unwrap()));
+    assert_eq!(7, cpu.get_register(1));
+    assert_eq!("no key held\n", debugger.execute(&mut cpu, parse_command("key").unwrap()));
+}
+
+#[test]
+fn registers_and_memory_test() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new(600);
+
+    for command in ["set v3 AB", "set i 2EA", "set pc 204", "set st 10", "poke 2EA F0 90 41"] {
+        assert_eq!("", debugger.execute(&mut cpu, parse_command(command).unwrap()));
+    }
+    assert_eq!("0x100 doesn't fit in a byte\n", debugger.execute(&mut cpu, parse_command("set v0 100").unwrap()));
+    assert_eq!("the stack holds at most 16 entries\n", debugger.execute(&mut cpu, parse_command("set sp 11").unwrap()));
+
+    assert_eq!("\
+PC=204 I=2EA SP=0 DT=00 ST=10
+V0=00 V1=00 V2=00 V3=AB V4=00 V5=00 V6=00 V7=00
+V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00
+", debugger.execute(&mut cpu, DebugCommand::Registers));
+    assert_eq!("2EA  F0 90 41 00                                      ..A.\n", debugger.execute(&mut cpu, parse_command("x 2EA 4").unwrap()));
+    assert_eq!("0x1000 is past the end of the memory\n", debugger.execute(&mut cpu, parse_command("x 1000").unwrap()));
+
+    let listing = debugger.execute(&mut cpu, parse_command("list").unwrap());
+    assert_eq!(vec![
+        "    1FE  00 00        SYS 0x000",
+        "    200  60 05        LD V0, 0x05",
+        "    202  22 08        CALL 0x208",
+        "=>  204  12 04        JP 0x204",
+    ], listing.lines().take(4).collect::<Vec<&str>>());
+}
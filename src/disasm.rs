@@ -0,0 +1,166 @@
+use crate::instruction::{self, Instruction, Operand};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// data bytes listed per DB line
+const BYTES_PER_LINE: usize = 8;
+
+/// A ROM split into code and data.
+/// The code is found by following the execution from the load address through jumps, calls and skips,
+/// everything that can't be reached that way is listed as data
+pub struct Disassembly {
+    origin: usize,
+    rom: Vec<u8>,
+    // instructions by address
+    code: BTreeMap<usize, Instruction>,
+    // names of the jump, call and I targets
+    labels: BTreeMap<usize, String>,
+}
+
+impl Disassembly {
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// The instruction starting at `addr`, if `addr` was found to be code
+    pub fn instruction(&self, addr: usize) -> Option<&Instruction> {
+        self.code.get(&addr)
+    }
+
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    fn format_line(&self, addr: usize, text: &str, size: usize) -> String {
+        let offset = addr - self.origin;
+        let bytes: Vec<String> = self.rom[offset..offset + size].iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("    {:<27} ; {:03X}  {}\n", text, addr, bytes.join(" "))
+    }
+
+    /// The listing with `annotation(addr, size)` in front of each line, the annotations must all have the same width
+    pub fn annotated(&self, annotation: &dyn Fn(usize, usize) -> String) -> String {
+        let mut out = String::new();
+        let _ = self.write_listing(&mut out, annotation);
+        out
+    }
+
+    fn write_listing(&self, f: &mut dyn fmt::Write, annotation: &dyn Fn(usize, usize) -> String) -> fmt::Result {
+        let end = self.origin + self.rom.len();
+        let mut addr = self.origin;
+
+        while addr < end {
+            if let Some(label) = self.labels.get(&addr) {
+                writeln!(f, "{}{}:", " ".repeat(annotation(addr, 0).len()), label)?;
+            }
+
+            if let Some(instruction) = self.code.get(&addr) {
+                let text = instruction.format_with(&|target| self.labels.get(&(target as usize)).cloned());
+                write!(f, "{}{}", annotation(addr, instruction.size()), self.format_line(addr, &text, instruction.size()))?;
+                addr += instruction.size();
+                continue;
+            }
+
+            // data runs until the next code, label or the end of the line
+            let mut size = 1;
+            while size < BYTES_PER_LINE && addr + size < end
+                && !self.code.contains_key(&(addr + size)) && !self.labels.contains_key(&(addr + size)) {
+                size += 1;
+            }
+            let offset = addr - self.origin;
+            let values: Vec<String> = self.rom[offset..offset + size].iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            write!(f, "{}{}", annotation(addr, size), self.format_line(addr, &format!("DB {}", values.join(", ")), size))?;
+            addr += size;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Disassembly {
+    /// Listing that the assembler reads back : one instruction or DB line each, with the address and the raw bytes as a comment
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_listing(f, &|_, _| String::new())
+    }
+}
+
+/// Splits `rom`, loaded at `origin`, into code and data and names the addresses it refers to
+pub fn disassemble(rom: &[u8], origin: usize) -> Disassembly {
+    let end = origin + rom.len();
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut claimed = vec![false; rom.len()];
+    let mut calls = Vec::new();
+    let mut jumps = Vec::new();
+    let mut data = Vec::new();
+
+    let mut pending = vec![origin];
+    while let Some(addr) = pending.pop() {
+        if addr < origin || addr >= end || code.contains_key(&addr) {
+            continue;
+        }
+
+        let offset = addr - origin;
+        let instruction = match instruction::decode(&rom[offset..]) {
+            // the interpreter can't run 0NNN either, it is most likely data
+            Some(instruction) if instruction.spec.mnemonic != "SYS" => instruction,
+            _ => continue,
+        };
+        // bytes already decoded as part of another instruction
+        if claimed[offset..offset + instruction.size()].iter().any(|claimed| *claimed) {
+            continue;
+        }
+        claimed[offset..offset + instruction.size()].iter_mut().for_each(|claimed| *claimed = true);
+
+        let next = addr + instruction.size();
+        match (instruction.spec.mnemonic, instruction.operands.first(), instruction.target()) {
+            ("CALL", _, Some(target)) => {
+                calls.push(target as usize);
+                pending.push(target as usize);
+                pending.push(next);
+            },
+            // JP V0, NNN goes somewhere only known at run time
+            ("JP", Some(Operand::Fixed(_)), Some(target)) => jumps.push(target as usize),
+            ("JP", _, Some(target)) => {
+                jumps.push(target as usize);
+                pending.push(target as usize);
+            },
+            ("LD", _, Some(target)) => {
+                data.push(target as usize);
+                pending.push(next);
+            },
+            _ if instruction.is_skip() => {
+                // the skipped instruction is 4 bytes long when it is F000 NNNN
+                let skipped = match rom.get(next - origin..next - origin + 2) {
+                    Some([0xF0, 0x00]) => 4,
+                    _ => 2,
+                };
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            _ if instruction.ends_flow() => (),
+            _ => pending.push(next),
+        };
+
+        code.insert(addr, instruction);
+    }
+
+    // labels can only go where a line starts : on an instruction or on a data byte
+    let mut labels = BTreeMap::new();
+    let line_start = |addr: usize| addr >= origin && addr < end && (code.contains_key(&addr) || !claimed[addr - origin]);
+    for (targets, prefix) in [(&data, "data"), (&jumps, "L"), (&calls, "sub")] {
+        for target in targets.iter().filter(|target| line_start(**target)) {
+            labels.insert(*target, format!("{}_{:03X}", prefix, target));
+        }
+    }
+
+    Disassembly {
+        origin,
+        rom: rom.to_vec(),
+        code,
+        labels,
+    }
+}
+
+#[cfg(test)]
+#[path = "disasm_test.rs"]
+mod disasm_test;
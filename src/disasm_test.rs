@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn code_and_data_test() {
+    let rom = [
+        0xA2, 0x0C, // 200 LD I, data
+        0x22, 0x08, // 202 CALL sub
+        0x12, 0x04, // 204 JP 204
+        0x00, 0x00, // 206 never reached
+        0xD0, 0x13, // 208 DRW V0, V1, 3
+        0x00, 0xEE, // 20A RET
+        0xF0, 0x90, 0xF0, // 20C sprite
+    ];
+    let disassembly = disassemble(&rom, 0x200);
+
+    assert!(disassembly.instruction(0x200).is_some());
+    assert!(disassembly.instruction(0x206).is_none());
+    assert!(disassembly.instruction(0x20C).is_none());
+    assert_eq!(Some("data_20C"), disassembly.label(0x20C));
+    assert_eq!(Some("sub_208"), disassembly.label(0x208));
+    assert_eq!(Some("L_204"), disassembly.label(0x204));
+
+    let listing = disassembly.to_string();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!("    LD I, data_20C              ; 200  A2 0C", lines[0]);
+    assert_eq!("L_204:", lines[2]);
+    assert_eq!("    JP L_204                    ; 204  12 04", lines[3]);
+    assert_eq!("    DB 0x00, 0x00               ; 206  00 00", lines[4]);
+    assert_eq!("sub_208:", lines[5]);
+    assert_eq!("    DB 0xF0, 0x90, 0xF0         ; 20C  F0 90 F0", lines[9]);
+}
+
+#[test]
+fn skip_long_load_test() {
+    // SE V0, 0 skips the 4 bytes of F000 NNNN so both 202 and 208 are code
+    let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xEE, 0x00, 0xEE];
+    let disassembly = disassemble(&rom, 0x200);
+
+    assert_eq!("LD I, LONG 0x1234", disassembly.instruction(0x202).unwrap().to_string());
+    assert!(disassembly.instruction(0x206).is_some());
+    assert!(disassembly.instruction(0x204).is_none());
+}
@@ -1,104 +1,246 @@
-use sdl2::Sdl;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use crate::error::EmulatorError;
+use crate::savestate::{StateReader, StateWriter};
+
+use std::str::FromStr;
 
 // original Chip8 width
-const WIDTH: u32 = 64;
+pub const WIDTH: u32 = 64;
 // original Chip8 height
-const HEIGHT: u32 = 32;
-const RESIZE: u32 = 10;
+pub const HEIGHT: u32 = 32;
+// SUPER-CHIP high resolution width
+pub const HIRES_WIDTH: u32 = 128;
+// SUPER-CHIP high resolution height
+pub const HIRES_HEIGHT: u32 = 64;
+
+/// A colour used by the renderers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// Colours of the pixel values : background, plane 1, plane 2 and both planes
+pub const DEFAULT_PALETTE: [Rgb; 4] = [Rgb(0x00, 0x00, 0x00), Rgb(0xFF, 0xFF, 0xFF), Rgb(0xAA, 0xAA, 0xAA), Rgb(0x55, 0x55, 0x55)];
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses `RRGGBB` or `#RRGGBB`
+    fn from_str(color: &str) -> Result<Rgb, String> {
+        let hex = color.trim_start_matches('#');
+        if hex.len() != 6 {
+            return Err(format!("invalid colour '{}'", color));
+        }
+
+        let value = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour '{}'", color))?;
+        Ok(Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+}
+
+/// Receives the finished frames produced by the core.
+/// Implementations only read the framebuffer, the collision logic stays in `Display::draw`
+pub trait VideoSink {
+    fn present(&mut self, frame: &Display) -> Result<(), String>;
+}
 
+/// Video sink that drops every frame, for headless runs
+pub struct NullVideo;
+
+impl VideoSink for NullVideo {
+    fn present(&mut self, _frame: &Display) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Video sink that keeps a copy of every presented frame, mostly for tests
+#[derive(Default)]
+pub struct MemoryVideo {
+    frames: Vec<Vec<u8>>,
+}
+
+impl MemoryVideo {
+    pub fn new() -> MemoryVideo {
+        MemoryVideo {
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn frames(&self) -> &[Vec<u8>] {
+        &self.frames
+    }
+
+    pub fn last_frame(&self) -> Option<&[u8]> {
+        self.frames.last().map(|frame| frame.as_slice())
+    }
+}
+
+impl VideoSink for MemoryVideo {
+    fn present(&mut self, frame: &Display) -> Result<(), String> {
+        self.frames.push(frame.pixels().to_vec());
+        Ok(())
+    }
+}
+
+/// The framebuffer, 64x32 or 128x64 pixels in the SUPER-CHIP high resolution mode.
+/// Each pixel holds one bit per XO-CHIP plane so its value is between 0 and 3, plain CHIP-8 only uses the first plane
 pub struct Display {
-    canvas: Canvas<Window>,
-    display: [u8; 32*64],
+    // big enough for the high resolution, only the first width * height pixels are used
+    display: [u8; (HIRES_WIDTH * HIRES_HEIGHT) as usize],
+    hires: bool,
+    // planes affected by drawing, clearing and scrolling, bit 0 is the first plane
+    planes: u8,
+    // set whenever the framebuffer changes so frontends know when to redraw
+    dirty: bool,
 }
 
-impl Display {
-    pub fn new(sdl_context: &Sdl) -> Display {
-        let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem.window("Chip8", WIDTH * RESIZE, HEIGHT * RESIZE)
-                                    .position_centered()
-                                    .build()
-                                    .unwrap();
-        let canvas = window.into_canvas().build().unwrap();
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl Display {
+    pub fn new() -> Display {
         Display {
-            canvas: canvas,
-            display: [0u8; 32*64],
+            display: [0u8; (HIRES_WIDTH * HIRES_HEIGHT) as usize],
+            hires: false,
+            planes: 1,
+            dirty: true,
         }
     }
 
-    pub fn clear_screen(&mut self) -> Result<bool, String> {
+    /// Clears the selected planes
+    pub fn clear_screen(&mut self) {
         // clear the collision detection display
         for elem in self.display.iter_mut() {
-            *elem = 0u8;
+            *elem &= !self.planes;
         }
 
-        // clear the actual screen
-        self.canvas.set_draw_color(Color::RGB(0u8, 0u8, 0u8));
-        self.canvas.clear();
-        self.canvas.present();
+        self.dirty = true;
+    }
+
+    /// Copy of the pixels of the current resolution, see pixels()
+    pub fn get_display(&self) -> Vec<u8> {
+        self.pixels().to_vec()
+    }
+
+    /// Replaces the pixels of the current resolution, `display` must hold width * height pixels
+    pub fn set_display(&mut self, display: &[u8]) {
+        let size = (self.width() * self.height()) as usize;
+        self.display[..size].copy_from_slice(display);
+        self.dirty = true;
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switches between 64x32 and 128x64, every plane is cleared like on the SUPER-CHIP
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = [0u8; (HIRES_WIDTH * HIRES_HEIGHT) as usize];
+        self.dirty = true;
+    }
 
-        Ok(true)
+    pub fn get_planes(&self) -> u8 {
+        self.planes
     }
 
-    pub fn initialize(&mut self) {
-        self.canvas.clear();
-        self.canvas.present();
+    /// Selects the planes affected by the next operations, 0 to 3
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
     }
 
-    pub fn get_display(&self) -> [u8; 32 * 64] {
-        self.display
+    pub fn width(&self) -> u32 {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
     }
 
-    pub fn set_display(&mut self, display: [u8; 32 * 64]) {
-        self.display = display;
+    pub fn height(&self) -> u32 {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
     }
 
-    pub fn draw(&mut self, x: u32, y: u32, buff: u8) -> Result<bool, String> {
+    /// Pixels in row-major order, 0 = off, otherwise the planes the pixel is lit in
+    pub fn pixels(&self) -> &[u8] {
+        &self.display[..(self.width() * self.height()) as usize]
+    }
 
-        let x = x % WIDTH;
-        let y = y % HEIGHT;
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        self.display[self.coord_to_matrix(x, y)]
+    }
+
+    /// Returns whether the framebuffer changed since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+
+    /// XORs the 8 pixels of `buff` in `plane` (1 or 2) on row `y` starting at column `x`.
+    /// Pixels past the right edge are dropped when `clip` is set, otherwise they wrap to the left edge of the same row.
+    /// Returns true if a lit pixel was turned off
+    pub fn draw(&mut self, x: u32, y: u32, buff: u8, plane: u8, clip: bool) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let y = y % height;
 
         let mut collision = false;
         for col in 0..8u32 {
+            if clip && x + col >= width {
+                break;
+            }
+
             let bit = (buff >> (7 - col)) & 0x01;
-            let display_coord = self.coord_to_matrix(x + col, y) % 2048;
-
-            // if there is a pixel flip set the flag and continue since we also know we don't have to draw anything
-            collision |= self.display[display_coord] == 1 && bit == 1;   
-            self.display[display_coord] ^= bit;
-            
-            self.set_color(self.display[display_coord]);
-
-            // compute the new position relative to the resized axes
-            let x_position = (x + col) * RESIZE;
-            let y_position = y * RESIZE;
-
-            match self.canvas.fill_rect(Rect::new(x_position as i32, y_position as i32, RESIZE, RESIZE)) {
-                Ok(_) => (),
-                Err(err) => return Err(err),
-            };
+            let display_coord = self.coord_to_matrix((x + col) % width, y);
+
+            // if there is a pixel flip set the flag
+            collision |= self.display[display_coord] & plane != 0 && bit == 1;
+            self.display[display_coord] ^= bit * plane;
         }
 
-        self.canvas.present();
+        self.dirty = true;
 
-        Ok(collision)
+        collision
     }
 
-    fn coord_to_matrix(&self, x: u32, y: u32) -> usize {
-        (x + y * WIDTH) as usize
+    /// Moves the selected planes by `dx` columns and `dy` rows, the pixels that come in are cleared
+    pub fn scroll(&mut self, dx: i32, dy: i32) {
+        let (width, height) = (self.width() as i32, self.height() as i32);
+        let previous = self.display;
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let source = if source_x >= 0 && source_x < width && source_y >= 0 && source_y < height {
+                    previous[self.coord_to_matrix(source_x as u32, source_y as u32)]
+                } else {
+                    0
+                };
+
+                let coord = self.coord_to_matrix(x as u32, y as u32);
+                self.display[coord] = (previous[coord] & !self.planes) | (source & self.planes);
+            }
+        }
+
+        self.dirty = true;
     }
 
-    fn set_color(&mut self, bit: u8) {
-        if bit == 1 {
-            // if bit = 1 -> set color to white
-            self.canvas.set_draw_color(Color::RGB(255u8, 255u8, 255u8));
-        } else {
-            // if bit = 0 -> set color to black => clear it
-            self.canvas.set_draw_color(Color::RGB(0u8, 0u8, 0u8));
-        }
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.put_bool(self.hires);
+        out.put_u8(self.planes);
+        out.put_bytes(&self.display);
     }
-}
\ No newline at end of file
+
+    /// Restores a display saved by write_state, the frame is marked dirty so it is presented again
+    pub fn read_state(input: &mut StateReader) -> Result<Display, EmulatorError> {
+        let hires = input.get_bool()?;
+        let planes = input.get_u8()? & 0x3;
+        let mut display = [0u8; (HIRES_WIDTH * HIRES_HEIGHT) as usize];
+        display.copy_from_slice(input.get_bytes((HIRES_WIDTH * HIRES_HEIGHT) as usize)?);
+
+        Ok(Display {
+            display,
+            hires,
+            planes,
+            dirty: true,
+        })
+    }
+
+    fn coord_to_matrix(&self, x: u32, y: u32) -> usize {
+        (x + y * self.width()) as usize
+    }
+}
@@ -0,0 +1,73 @@
+use crate::breakpoint::BreakReason;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while loading or running a program
+#[derive(Debug)]
+pub enum EmulatorError {
+    /// the opcode at `pc` isn't part of the instruction set
+    UnknownOpcode { pc: usize, opcode: u16 },
+    /// 2NNN at `pc` called a subroutine with every stack slot in use
+    StackOverflow { pc: usize },
+    /// 00EE at `pc` returned with an empty stack
+    StackUnderflow { pc: usize },
+    /// an instruction accessed memory past the end of the address space
+    MemoryOutOfBounds { addr: usize },
+    /// the ROM doesn't fit in the memory available after its load address
+    RomTooLarge { size: usize, max: usize },
+    Io(io::Error),
+    /// the data isn't a save state this version can read
+    InvalidSaveState(String),
+    /// the save state was taken with another ROM or another machine configuration
+    IncompatibleSaveState(String),
+    /// failure reported by a video, input or audio backend
+    Backend(String),
+}
+
+/// What happened during a step when it didn't fail
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepOutcome {
+    /// the CPU keeps running
+    Continue,
+    /// the fetch is halted by FX0A until a key is pressed
+    WaitingForKey,
+    /// the input source asked to quit
+    Quit,
+    /// the program ended itself with 00FD
+    Exit,
+    /// a breakpoint or a watchpoint stopped the execution
+    Break(BreakReason),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode 0x{:04X} at 0x{:03X}", opcode, pc),
+            EmulatorError::StackOverflow { pc } => write!(f, "stack overflow at 0x{:03X}", pc),
+            EmulatorError::StackUnderflow { pc } => write!(f, "stack underflow at 0x{:03X}", pc),
+            EmulatorError::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds at 0x{:X}", addr),
+            EmulatorError::RomTooLarge { size, max } => write!(f, "ROM is {} bytes but only {} bytes are available", size, max),
+            EmulatorError::Io(err) => write!(f, "{}", err),
+            EmulatorError::InvalidSaveState(reason) => write!(f, "invalid save state : {}", reason),
+            EmulatorError::IncompatibleSaveState(reason) => write!(f, "incompatible save state : {}", reason),
+            EmulatorError::Backend(err) => write!(f, "backend error : {}", err),
+        }
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmulatorError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(err: io::Error) -> EmulatorError {
+        EmulatorError::Io(err)
+    }
+}
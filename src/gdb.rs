@@ -0,0 +1,447 @@
+use crate::breakpoint::{Access, BreakReason, Breakpoint, Register, REGISTERS};
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::error::{EmulatorError, StepOutcome};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+// instructions run between two checks for a Ctrl-C from the client
+const INTERRUPT_CHECK_INTERVAL: usize = 1000;
+// largest packet we accept, announced in qSupported
+const PACKET_SIZE: usize = 0x1000;
+
+// signals of the stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Register layout given to GDB, the numbers follow breakpoint::REGISTERS
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// size in bytes of a register in the `g` packet, I and PC are 16 bits
+fn register_size(register: Register) -> usize {
+    match register {
+        Register::I | Register::Pc => 2,
+        _ => 1,
+    }
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes().chunks(2).map(|pair| match pair {
+        [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+        _ => None,
+    }).collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// registers are sent little endian like most GDB targets
+fn encode_register(register: Register, value: usize) -> String {
+    encode_hex(&value.to_le_bytes()[..register_size(register)])
+}
+
+fn decode_register(register: Register, bytes: &[u8]) -> Option<usize> {
+    if bytes.len() != register_size(register) {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0, |value, byte| (value << 8) | *byte as usize))
+}
+
+/// `addr,length` of the m, M, Z and z packets, in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+/// What a packet read from the client turned out to be
+enum Incoming {
+    Packet(String),
+    /// Ctrl-C, a single 0x03 byte outside of a packet
+    Interrupt,
+    Closed,
+}
+
+/// GDB remote serial protocol server for one client, controlling the CPU on its behalf.
+/// The timers are ticked as in the debugger, the breakpoints go through the CPU
+pub struct GdbStub<'a> {
+    cpu: &'a mut Cpu,
+    debugger: Debugger,
+    stream: TcpStream,
+    // set by QStartNoAckMode, packets are no longer acknowledged
+    no_ack: bool,
+    // core breakpoint id of each Z packet, by type, address and length
+    breakpoints: HashMap<(u8, usize, usize), usize>,
+    // bytes received while the program ran, read before the stream
+    pending: VecDeque<u8>,
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(cpu: &'a mut Cpu, stream: TcpStream, instructions_per_second: u32) -> GdbStub<'a> {
+        GdbStub {
+            cpu,
+            debugger: Debugger::new(instructions_per_second),
+            stream,
+            no_ack: false,
+            breakpoints: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0u8; 1];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn read_packet(&mut self) -> io::Result<Incoming> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(Incoming::Closed),
+                Some(0x03) => return Ok(Incoming::Interrupt),
+                Some(b'$') => (),
+                // acknowledgements and noise between packets
+                Some(_) => continue,
+            };
+
+            let mut data = Vec::new();
+            let mut checksum = 0u8;
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(Incoming::Closed),
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        checksum = checksum.wrapping_add(byte);
+                        data.push(byte);
+                    },
+                };
+            }
+            let mut expected = [0u8; 2];
+            for digit in expected.iter_mut() {
+                match self.read_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(Incoming::Closed),
+                };
+            }
+
+            let valid = std::str::from_utf8(&expected).ok().and_then(|text| u8::from_str_radix(text, 16).ok()) == Some(checksum);
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Incoming::Packet(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        // the characters with a meaning in the framing are escaped with } and xored with 0x20
+        let mut escaped = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+                _ => escaped.push(byte),
+            };
+        }
+        let checksum = escaped.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    /// Answers the client until it detaches, kills the program or disconnects
+    pub fn run(&mut self) -> Result<(), EmulatorError> {
+        loop {
+            let packet = match self.read_packet()? {
+                Incoming::Packet(packet) => packet,
+                // Ctrl-C while stopped, there is nothing to interrupt
+                Incoming::Interrupt => {
+                    self.send_packet(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                },
+                Incoming::Closed => return Ok(()),
+            };
+
+            match packet.as_str() {
+                "D" => {
+                    self.send_packet("OK")?;
+                    return Ok(());
+                },
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.handle(&packet)?;
+                    self.send_packet(&reply)?;
+                },
+            };
+        }
+    }
+
+    /// The reply to a packet, empty for unsupported packets
+    fn handle(&mut self, packet: &str) -> Result<String, EmulatorError> {
+        // packets are decoded lossily, the command may not be ASCII
+        let split = packet.chars().next().map_or(0, |command| command.len_utf8());
+        let reply = match packet.split_at(split) {
+            ("?", _) => format!("S{:02x}", SIGTRAP),
+            ("g", _) => REGISTERS.iter().map(|register| encode_register(*register, register.read(self.cpu))).collect(),
+            ("G", values) => self.write_registers(values),
+            ("p", number) => match usize::from_str_radix(number, 16).ok().and_then(Register::from_index) {
+                Some(register) => encode_register(register, register.read(self.cpu)),
+                None => String::from("E00"),
+            },
+            ("P", assignment) => self.write_register(assignment),
+            ("m", range) => self.read_memory(range),
+            ("M", write) => self.write_memory(write),
+            ("s", _) => {
+                self.cpu.resume();
+                let outcome = self.debugger.step(self.cpu);
+                self.stop_reply(outcome)
+            },
+            ("c", _) => self.resume()?,
+            ("Z", args) => self.add_breakpoint(args),
+            ("z", args) => self.remove_breakpoint(args),
+            ("H", _) => String::from("OK"),
+            ("Q", _) if packet == "QStartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            },
+            ("q", _) | ("Q", _) => self.query(packet),
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match parse_range(range) {
+                Some(range) => range,
+                None => return String::from("E00"),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let end = offset.saturating_add(length).min(xml.len());
+            let chunk = &xml[offset.min(end)..end];
+            let more = if end < xml.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, String::from_utf8_lossy(chunk));
+        }
+
+        match packet {
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    fn write_registers(&mut self, values: &str) -> String {
+        let bytes = match decode_hex(values) {
+            Some(bytes) => bytes,
+            None => return String::from("E00"),
+        };
+
+        // every value is checked before any register changes
+        let mut values = Vec::with_capacity(REGISTERS.len());
+        let mut offset = 0;
+        for register in REGISTERS.iter() {
+            let size = register_size(*register);
+            match bytes.get(offset..offset + size).and_then(|bytes| decode_register(*register, bytes)) {
+                Some(value) if register.check(self.cpu, value).is_ok() => values.push(value),
+                _ => return String::from("E00"),
+            };
+            offset += size;
+        }
+
+        match REGISTERS.iter().zip(values).try_for_each(|(register, value)| register.write(self.cpu, value)) {
+            Ok(()) => String::from("OK"),
+            Err(_) => String::from("E00"),
+        }
+    }
+
+    fn write_register(&mut self, assignment: &str) -> String {
+        let written = assignment.split_once('=').and_then(|(number, value)| {
+            let register = Register::from_index(usize::from_str_radix(number, 16).ok()?)?;
+            let value = decode_register(register, &decode_hex(value)?)?;
+            register.write(self.cpu, value).ok()
+        });
+
+        match written {
+            Some(()) => String::from("OK"),
+            None => String::from("E00"),
+        }
+    }
+
+    fn read_memory(&self, range: &str) -> String {
+        let memory = self.cpu.get_memory().as_slice();
+        match parse_range(range) {
+            Some((addr, length)) if addr.checked_add(length).is_some_and(|end| end <= memory.len()) => encode_hex(&memory[addr..addr + length]),
+            _ => String::from("E14"),
+        }
+    }
+
+    fn write_memory(&mut self, write: &str) -> String {
+        let (range, data) = match write.split_once(':') {
+            Some(parts) => parts,
+            None => return String::from("E00"),
+        };
+        let (addr, bytes) = match (parse_range(range), decode_hex(data)) {
+            (Some((addr, length)), Some(bytes)) if bytes.len() == length => (addr, bytes),
+            _ => return String::from("E00"),
+        };
+        if addr.checked_add(bytes.len()).is_none_or(|end| end > self.cpu.get_memory().len()) {
+            return String::from("E14");
+        }
+
+        let memory = self.cpu.get_memory_mut();
+        for (offset, byte) in bytes.iter().enumerate() {
+            memory[addr + offset] = *byte;
+        }
+
+        String::from("OK")
+    }
+
+    /// Z0 and Z1 are breakpoints, Z2, Z3 and Z4 watch writes, reads and both.
+    /// The error is the reply : empty for the other kinds, E00 for a malformed or overflowing range
+    fn breakpoint_kind(args: &str) -> Result<(u8, usize, usize, Breakpoint), String> {
+        let malformed = || String::from("E00");
+        let (kind, range) = args.split_once(',').ok_or_else(malformed)?;
+        let (addr, length) = parse_range(range).ok_or_else(malformed)?;
+        let last = addr.checked_add(length.max(1) - 1).ok_or_else(malformed)?;
+        let kind = kind.parse::<u8>().map_err(|_| malformed())?;
+        let breakpoint = match kind {
+            0 | 1 => Breakpoint::Address(addr),
+            2 => Breakpoint::Memory { start: addr, end: last, access: Access::Write },
+            3 => Breakpoint::Memory { start: addr, end: last, access: Access::Read },
+            4 => Breakpoint::Memory { start: addr, end: last, access: Access::ReadWrite },
+            _ => return Err(String::new()),
+        };
+
+        Ok((kind, addr, length, breakpoint))
+    }
+
+    fn add_breakpoint(&mut self, args: &str) -> String {
+        match GdbStub::breakpoint_kind(args) {
+            Ok((kind, addr, length, breakpoint)) => {
+                if !self.breakpoints.contains_key(&(kind, addr, length)) {
+                    let id = self.cpu.get_breakpoints_mut().add(breakpoint);
+                    self.breakpoints.insert((kind, addr, length), id);
+                }
+                String::from("OK")
+            },
+            Err(reply) => reply,
+        }
+    }
+
+    fn remove_breakpoint(&mut self, args: &str) -> String {
+        match GdbStub::breakpoint_kind(args) {
+            Ok((kind, addr, length, _)) => {
+                if let Some(id) = self.breakpoints.remove(&(kind, addr, length)) {
+                    self.cpu.get_breakpoints_mut().remove(id);
+                }
+                String::from("OK")
+            },
+            Err(reply) => reply,
+        }
+    }
+
+    /// Runs until a breakpoint, the end of the program, an error or a Ctrl-C from the client
+    fn resume(&mut self) -> Result<String, EmulatorError> {
+        self.cpu.resume();
+
+        loop {
+            for _ in 0..INTERRUPT_CHECK_INTERVAL {
+                let outcome = self.debugger.step(self.cpu);
+                if !matches!(outcome, Ok(StepOutcome::Continue) | Ok(StepOutcome::WaitingForKey)) {
+                    return Ok(self.stop_reply(outcome));
+                }
+            }
+
+            if self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    /// Whether the client sent a Ctrl-C, without waiting for it.
+    /// Other bytes are kept for the next packet
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8; 1];
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(1) if byte[0] == 0x03 => Ok(true),
+            Ok(1) => {
+                self.pending.push_back(byte[0]);
+                Ok(false)
+            },
+            // a closed connection stops the execution too, the next read notices it
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn stop_reply(&self, outcome: Result<StepOutcome, EmulatorError>) -> String {
+        match outcome {
+            Ok(StepOutcome::Exit) => String::from("W00"),
+            Ok(StepOutcome::Break(BreakReason::Memory { id, access, .. })) => {
+                let watch = self.cpu.get_breakpoints().iter().find_map(|(entry, breakpoint)| match breakpoint {
+                    Breakpoint::Memory { access: Access::Read, .. } if *entry == id => Some("rwatch"),
+                    Breakpoint::Memory { access: Access::ReadWrite, .. } if *entry == id => Some("awatch"),
+                    _ => None,
+                }).unwrap_or("watch");
+                format!("T{:02x}{}:{:x};", SIGTRAP, watch, access.addr)
+            },
+            Ok(_) => format!("S{:02x}", SIGTRAP),
+            Err(EmulatorError::UnknownOpcode { .. }) => format!("S{:02x}", SIGILL),
+            Err(_) => format!("S{:02x}", SIGSEGV),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "gdb_test.rs"]
+mod gdb_test;
@@ -0,0 +1,145 @@
+use super::*;
+
+use std::net::TcpListener;
+use std::thread;
+
+// V0 = 5, I = 0x300, stores V0 at 0x300 then loops on itself
+const ROM: [u8; 8] = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+/// Scripted GDB side of the connection
+struct Client {
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+        if self.ack {
+            assert_eq!(b'+', self.byte());
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0u8; 1];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(b'$', self.byte());
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            };
+        }
+        let checksum = format!("{}{}", self.byte() as char, self.byte() as char);
+        assert_eq!(format!("{:02x}", data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))), checksum);
+        if self.ack {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+}
+
+/// Runs the stub on this thread, the Cpu can't leave it, and `script` as the client
+fn serve<F: FnOnce(&mut Client) + Send + 'static>(script: F) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&ROM).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut client = Client { stream: TcpStream::connect(addr).unwrap(), ack: true };
+        script(&mut client);
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    GdbStub::new(&mut cpu, stream, 600).run().unwrap();
+    client.join().unwrap();
+    cpu
+}
+
+#[test]
+fn registers_and_memory_test() {
+    let cpu = serve(|client| {
+        assert!(client.request("qSupported:multiprocess+;swbreak+").starts_with("PacketSize=1000;"));
+        assert_eq!("S05", client.request("?"));
+        assert_eq!(format!("{}00000002000000", "00".repeat(16)), client.request("g"));
+        // SP is past the stack, nothing is written
+        assert_eq!("E00", client.request(&format!("G{}00000002ff0000", "11".repeat(16))));
+        assert_eq!(format!("{}00000002000000", "00".repeat(16)), client.request("g"));
+
+        assert_eq!("S05", client.request("s"));
+        assert_eq!("05", client.request("p0"));
+        assert_eq!("0202", client.request("p11"));
+        assert_eq!("E00", client.request("p15"));
+
+        assert_eq!("OK", client.request("P0=2a"));
+        assert_eq!("OK", client.request("P10=3412"));
+        assert_eq!("OK", client.request("M300,2:beef"));
+        assert_eq!("beef00", client.request("m300,3"));
+        assert_eq!("E14", client.request("mfff,2"));
+        assert_eq!("E00", client.request("M300,2:be"));
+        assert_eq!("E14", client.request("Mffffffffffffffff,1:00"));
+        assert_eq!("E14", client.request("mffffffffffffffff,2"));
+        assert_eq!("E00", client.request("Z2,ffffffffffffffff,2"));
+        assert_eq!("l", client.request("qXfer:features:read:target.xml:ffffffffffffffff,10"));
+        assert_eq!("", client.request("vMustReplyEmpty"));
+        assert_eq!("", client.request("\u{e9}"));
+
+        let xml = client.request("qXfer:features:read:target.xml:0,1f");
+        assert_eq!("m<?xml version=\"1.0\"?>\n<!DOCTYPE", xml);
+        assert!(client.request("qXfer:features:read:target.xml:0,1000").ends_with("</target>\n"));
+
+        assert_eq!("OK", client.request("QStartNoAckMode"));
+        client.ack = false;
+        assert_eq!("1", client.request("qAttached"));
+        assert_eq!("OK", client.request("D"));
+    });
+
+    assert_eq!(0x2A, cpu.get_register(0));
+    assert_eq!(0x1234, cpu.get_i());
+    assert_eq!(0xEF, cpu.get_memory()[0x301]);
+}
+
+#[test]
+fn breakpoints_test() {
+    let cpu = serve(|client| {
+        assert_eq!("OK", client.request("Z0,204,2"));
+        assert_eq!("S05", client.request("c"));
+        assert_eq!("0402", client.request("p11"));
+        assert_eq!("OK", client.request("z0,204,2"));
+
+        assert_eq!("OK", client.request("Z2,300,1"));
+        assert_eq!("T05watch:300;", client.request("c"));
+        assert_eq!("05", client.request("m300,1"));
+        assert_eq!("OK", client.request("z2,300,1"));
+        assert_eq!("", client.request("Z7,300,1"));
+
+        // nothing stops the loop but Ctrl-C
+        client.send("c");
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!("S02", client.reply());
+        assert_eq!("0602", client.request("p11"));
+
+        // a packet sent while the program runs is answered after the stop
+        client.send("c");
+        client.stream.write_all(b"$p11#d2\x03").unwrap();
+        assert_eq!("S02", client.reply());
+        assert_eq!(b'+', client.byte());
+        assert_eq!("0602", client.reply());
+        client.send("k");
+    });
+
+    assert!(cpu.get_breakpoints().is_empty());
+}
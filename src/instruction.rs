@@ -0,0 +1,211 @@
+use std::fmt;
+
+/// What an operand of an instruction is made of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperandKind {
+    /// register whose index is in bits 8-11
+    Vx,
+    /// register whose index is in bits 4-7
+    Vy,
+    /// 8 bits constant in bits 0-7
+    Byte,
+    /// 12 bits address in bits 0-11
+    Addr,
+    /// 4 bits constant in bits 0-3
+    Nibble,
+    /// 4 bits constant in bits 8-11, the planes of FN01
+    Planes,
+    /// 16 bits address stored in the word following the opcode, F000 NNNN
+    Long,
+    /// a keyword that is always written the same way, like I or DT
+    Fixed(&'static str),
+}
+
+/// One entry of the instruction table : the opcodes matching `pattern` under `mask`
+#[derive(Debug, PartialEq)]
+pub struct InstructionSpec {
+    pub mnemonic: &'static str,
+    pub pattern: u16,
+    pub mask: u16,
+    pub operands: &'static [OperandKind],
+}
+
+use OperandKind::{Vx, Vy, Byte, Addr, Nibble, Planes, Long, Fixed};
+
+macro_rules! spec {
+    ($mnemonic:expr, $pattern:expr, $mask:expr, [$($operand:expr),*]) => {
+        InstructionSpec { mnemonic: $mnemonic, pattern: $pattern, mask: $mask, operands: &[$($operand),*] }
+    };
+}
+
+/// Every instruction of CHIP-8, SUPER-CHIP 1.1 and XO-CHIP with Cowgod style mnemonics.
+/// Shared by the disassembler and the assembler, the first matching entry wins
+pub static INSTRUCTIONS: [InstructionSpec; 51] = [
+    spec!("CLS",   0x00E0, 0xFFFF, []),
+    spec!("RET",   0x00EE, 0xFFFF, []),
+    spec!("SCD",   0x00C0, 0xFFF0, [Nibble]),
+    spec!("SCU",   0x00D0, 0xFFF0, [Nibble]),
+    spec!("SCR",   0x00FB, 0xFFFF, []),
+    spec!("SCL",   0x00FC, 0xFFFF, []),
+    spec!("EXIT",  0x00FD, 0xFFFF, []),
+    spec!("LOW",   0x00FE, 0xFFFF, []),
+    spec!("HIGH",  0x00FF, 0xFFFF, []),
+    spec!("SYS",   0x0000, 0xF000, [Addr]),
+    spec!("JP",    0x1000, 0xF000, [Addr]),
+    spec!("CALL",  0x2000, 0xF000, [Addr]),
+    spec!("SE",    0x3000, 0xF000, [Vx, Byte]),
+    spec!("SNE",   0x4000, 0xF000, [Vx, Byte]),
+    spec!("SE",    0x5000, 0xF00F, [Vx, Vy]),
+    spec!("SAVE",  0x5002, 0xF00F, [Vx, Vy]),
+    spec!("LOAD",  0x5003, 0xF00F, [Vx, Vy]),
+    spec!("LD",    0x6000, 0xF000, [Vx, Byte]),
+    spec!("ADD",   0x7000, 0xF000, [Vx, Byte]),
+    spec!("LD",    0x8000, 0xF00F, [Vx, Vy]),
+    spec!("OR",    0x8001, 0xF00F, [Vx, Vy]),
+    spec!("AND",   0x8002, 0xF00F, [Vx, Vy]),
+    spec!("XOR",   0x8003, 0xF00F, [Vx, Vy]),
+    spec!("ADD",   0x8004, 0xF00F, [Vx, Vy]),
+    spec!("SUB",   0x8005, 0xF00F, [Vx, Vy]),
+    spec!("SHR",   0x8006, 0xF00F, [Vx, Vy]),
+    spec!("SUBN",  0x8007, 0xF00F, [Vx, Vy]),
+    spec!("SHL",   0x800E, 0xF00F, [Vx, Vy]),
+    spec!("SNE",   0x9000, 0xF00F, [Vx, Vy]),
+    spec!("LD",    0xA000, 0xF000, [Fixed("I"), Addr]),
+    spec!("JP",    0xB000, 0xF000, [Fixed("V0"), Addr]),
+    spec!("RND",   0xC000, 0xF000, [Vx, Byte]),
+    spec!("DRW",   0xD000, 0xF000, [Vx, Vy, Nibble]),
+    spec!("SKP",   0xE09E, 0xF0FF, [Vx]),
+    spec!("SKNP",  0xE0A1, 0xF0FF, [Vx]),
+    spec!("LD",    0xF000, 0xFFFF, [Fixed("I"), Long]),
+    spec!("PLANE", 0xF001, 0xF0FF, [Planes]),
+    spec!("AUDIO", 0xF002, 0xFFFF, []),
+    spec!("LD",    0xF007, 0xF0FF, [Vx, Fixed("DT")]),
+    spec!("LD",    0xF00A, 0xF0FF, [Vx, Fixed("K")]),
+    spec!("LD",    0xF015, 0xF0FF, [Fixed("DT"), Vx]),
+    spec!("LD",    0xF018, 0xF0FF, [Fixed("ST"), Vx]),
+    spec!("ADD",   0xF01E, 0xF0FF, [Fixed("I"), Vx]),
+    spec!("LD",    0xF029, 0xF0FF, [Fixed("F"), Vx]),
+    spec!("LD",    0xF030, 0xF0FF, [Fixed("HF"), Vx]),
+    spec!("LD",    0xF033, 0xF0FF, [Fixed("B"), Vx]),
+    spec!("PITCH", 0xF03A, 0xF0FF, [Vx]),
+    spec!("LD",    0xF055, 0xF0FF, [Fixed("[I]"), Vx]),
+    spec!("LD",    0xF065, 0xF0FF, [Vx, Fixed("[I]")]),
+    spec!("LD",    0xF075, 0xF0FF, [Fixed("R"), Vx]),
+    spec!("LD",    0xF085, 0xF0FF, [Vx, Fixed("R")]),
+];
+
+/// A decoded operand
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register(u8),
+    Byte(u8),
+    Address(u16),
+    Nibble(u8),
+    LongAddress(u16),
+    Fixed(&'static str),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(index) => write!(f, "V{:X}", index),
+            Operand::Byte(value) => write!(f, "0x{:02X}", value),
+            Operand::Address(addr) => write!(f, "0x{:03X}", addr),
+            Operand::Nibble(value) => write!(f, "{}", value),
+            Operand::LongAddress(addr) => write!(f, "LONG 0x{:04X}", addr),
+            Operand::Fixed(keyword) => write!(f, "{}", keyword),
+        }
+    }
+}
+
+/// A decoded instruction, displayed as its mnemonic
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub opcode: u16,
+    pub spec: &'static InstructionSpec,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    /// Length in bytes, 4 for F000 NNNN and 2 otherwise
+    pub fn size(&self) -> usize {
+        if self.spec.operands.contains(&Long) { 4 } else { 2 }
+    }
+
+    /// The address JP NNN, CALL NNN, SYS NNN and LD I, NNN refer to
+    pub fn target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|operand| match operand {
+            Operand::Address(addr) | Operand::LongAddress(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Whether the execution never goes on to the next instruction : RET, EXIT, JP
+    pub fn ends_flow(&self) -> bool {
+        matches!(self.spec.mnemonic, "RET" | "EXIT" | "JP")
+    }
+
+    /// Whether the next instruction can be skipped : SE, SNE, SKP, SKNP
+    pub fn is_skip(&self) -> bool {
+        matches!(self.spec.mnemonic, "SE" | "SNE" | "SKP" | "SKNP")
+    }
+
+    /// The text of the operands, `label` gives the name used for an address if it has one
+    pub fn format_with(&self, label: &dyn Fn(u16) -> Option<String>) -> String {
+        let operands: Vec<String> = self.operands.iter().map(|operand| match operand {
+            Operand::Address(addr) => label(*addr).unwrap_or_else(|| operand.to_string()),
+            Operand::LongAddress(addr) => label(*addr).map(|name| format!("LONG {}", name)).unwrap_or_else(|| operand.to_string()),
+            _ => operand.to_string(),
+        }).collect();
+
+        if operands.is_empty() {
+            self.spec.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.spec.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(&|_| None))
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, None for unknown opcodes or when bytes are missing
+pub fn decode(bytes: &[u8]) -> Option<Instruction> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+    let spec = INSTRUCTIONS.iter().find(|spec| opcode & spec.mask == spec.pattern)?;
+
+    let mut operands = Vec::new();
+    for kind in spec.operands {
+        operands.push(match kind {
+            Vx => Operand::Register(((opcode >> 8) & 0xF) as u8),
+            Vy => Operand::Register(((opcode >> 4) & 0xF) as u8),
+            Byte => Operand::Byte(opcode as u8),
+            Addr => Operand::Address(opcode & 0x0FFF),
+            Nibble => Operand::Nibble((opcode & 0xF) as u8),
+            Planes => Operand::Nibble(((opcode >> 8) & 0xF) as u8),
+            Long => {
+                if bytes.len() < 4 {
+                    return None;
+                }
+                Operand::LongAddress((bytes[2] as u16) << 8 | bytes[3] as u16)
+            },
+            Fixed(keyword) => Operand::Fixed(keyword),
+        });
+    }
+
+    Some(Instruction {
+        opcode,
+        spec,
+        operands,
+    })
+}
+
+#[cfg(test)]
+#[path = "instruction_test.rs"]
+mod instruction_test;
@@ -0,0 +1,47 @@
+use super::*;
+
+fn text(bytes: &[u8]) -> String {
+    decode(bytes).map(|instruction| instruction.to_string()).unwrap_or_default()
+}
+
+#[test]
+fn decode_test() {
+    assert_eq!("CLS", text(&[0x00, 0xE0]));
+    assert_eq!("SCD 4", text(&[0x00, 0xC4]));
+    assert_eq!("SYS 0x123", text(&[0x01, 0x23]));
+    assert_eq!("JP 0x2A4", text(&[0x12, 0xA4]));
+    assert_eq!("SE V3, 0x1F", text(&[0x33, 0x1F]));
+    assert_eq!("SE V3, V4", text(&[0x53, 0x40]));
+    assert_eq!("SAVE V2, V5", text(&[0x52, 0x52]));
+    assert_eq!("SHL VA, VB", text(&[0x8A, 0xBE]));
+    assert_eq!("LD I, 0x300", text(&[0xA3, 0x00]));
+    assert_eq!("JP V0, 0x300", text(&[0xB3, 0x00]));
+    assert_eq!("DRW V0, V1, 0", text(&[0xD0, 0x10]));
+    assert_eq!("SKNP V7", text(&[0xE7, 0xA1]));
+    assert_eq!("LD I, LONG 0xABCD", text(&[0xF0, 0x00, 0xAB, 0xCD]));
+    assert_eq!("PLANE 3", text(&[0xF3, 0x01]));
+    assert_eq!("LD HF, V2", text(&[0xF2, 0x30]));
+    assert_eq!("LD [I], VF", text(&[0xFF, 0x55]));
+    assert_eq!("LD V5, R", text(&[0xF5, 0x85]));
+
+    assert_eq!(None, decode(&[0x5A, 0xB1]));
+    assert_eq!(None, decode(&[0xF1, 0xFF]));
+    assert_eq!(None, decode(&[0xF0, 0x00, 0x12]));
+    assert_eq!(None, decode(&[0x12]));
+}
+
+#[test]
+fn instruction_info_test() {
+    let long = decode(&[0xF0, 0x00, 0xAB, 0xCD]).unwrap();
+    assert_eq!(4, long.size());
+    assert_eq!(Some(0xABCD), long.target());
+
+    let call = decode(&[0x22, 0x10]).unwrap();
+    assert_eq!(2, call.size());
+    assert_eq!(Some(0x210), call.target());
+    assert!(!call.ends_flow());
+    assert_eq!("CALL draw", call.format_with(&|_| Some(String::from("draw"))));
+
+    assert!(decode(&[0x00, 0xEE]).unwrap().ends_flow());
+    assert!(decode(&[0xE1, 0x9E]).unwrap().is_skip());
+}
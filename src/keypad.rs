@@ -1,83 +1,185 @@
-use sdl2::Sdl;
-use sdl2::EventPump;
-use sdl2::keyboard::{Keycode};
-use sdl2::event::Event;
+use crate::error::EmulatorError;
+use crate::savestate::{StateReader, StateWriter};
 
-pub struct Keypad {
-    event_pump: EventPump,
-    pressed_keys: [bool; 16],
+/// Source of the 16-key state the core reads through EX9E/EXA1 and FX0A
+pub trait InputSource {
+    /// Refreshes the key state. Returns Ok(false) when the user asked to quit
+    fn poll(&mut self) -> Result<bool, String>;
+
+    /// State of the 16 keys, indexed by key value
+    fn keys(&self) -> [bool; 16];
 }
 
-impl Keypad {
-    pub fn new(sdl_context: &Sdl) -> Keypad {
+/// Input source with no keys ever pressed, for headless runs
+pub struct NullInput;
 
-        let event_pump = sdl_context.event_pump().unwrap();
-        Keypad {
-            event_pump: event_pump,
+impl InputSource for NullInput {
+    fn poll(&mut self) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    fn keys(&self) -> [bool; 16] {
+        [false; 16]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScriptAction {
+    Press(u8),
+    Release(u8),
+    Quit,
+}
+
+/// One entry of an input script : `action` is applied on poll number `poll`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScriptEvent {
+    pub poll: u64,
+    pub action: ScriptAction,
+}
+
+/// Input source replaying a list of timed key events, used by scripts, replay files and tests
+pub struct ScriptedInput {
+    events: Vec<ScriptEvent>,
+    next_event: usize,
+    polls: u64,
+    pressed_keys: [bool; 16],
+}
+
+impl ScriptedInput {
+    pub fn new(mut events: Vec<ScriptEvent>) -> ScriptedInput {
+        events.sort_by_key(|event| event.poll);
+        ScriptedInput {
+            events,
+            next_event: 0,
+            polls: 0,
             pressed_keys: [false; 16],
         }
     }
 
-    pub fn update_pressed_keys(&mut self) -> Result<bool, ()> {
-        
-        self.pressed_keys = [false; 16];
-
-        for event in self.event_pump.poll_iter() {
-            match event {
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    match keycode {
-                        Keycode::Num1 => self.pressed_keys[0x1] = true,
-                        Keycode::Num2 => self.pressed_keys[0x2] = true,
-                        Keycode::Num3 => self.pressed_keys[0x3] = true,
-                        Keycode::Num4 => self.pressed_keys[0xC] = true,
-                        Keycode::Q => self.pressed_keys[0x4] = true,
-                        Keycode::W => self.pressed_keys[0x5] = true,
-                        Keycode::E => self.pressed_keys[0x6] = true,
-                        Keycode::R => self.pressed_keys[0xD] = true,
-                        Keycode::A => self.pressed_keys[0x7] = true,
-                        Keycode::S => self.pressed_keys[0x8] = true,
-                        Keycode::D => self.pressed_keys[0x9] = true,
-                        Keycode::F => self.pressed_keys[0xE] = true,
-                        Keycode::Z => self.pressed_keys[0xA] = true,
-                        Keycode::X => self.pressed_keys[0x0] = true,
-                        Keycode::C => self.pressed_keys[0xB] = true,
-                        Keycode::V => self.pressed_keys[0xF] = true,
-                        _ => continue,
-                    };
-                },
-                Event::Quit {..} => return Ok(false),
-                _ => continue,
+    /// Parses a script made of lines like `120 down 5`, `130 up 5` or `400 quit`.
+    /// The first number is the poll on which the action happens, `#` starts a comment
+    pub fn from_script(script: &str) -> Result<ScriptedInput, String> {
+        let mut events = Vec::new();
+
+        for (line_number, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |msg: &str| format!("line {} : {}", line_number + 1, msg);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let poll = words[0].parse::<u64>().map_err(|_| error("expected a poll number"))?;
+            let key = || -> Result<u8, String> {
+                let word = words.get(2).ok_or_else(|| error("missing key"))?;
+                match u8::from_str_radix(word.trim_start_matches("0x"), 16) {
+                    Ok(key) if key < 16 => Ok(key),
+                    _ => Err(error("key must be between 0 and F")),
+                }
             };
+            let action = match words.get(1) {
+                Some(&"down") => ScriptAction::Press(key()?),
+                Some(&"up") => ScriptAction::Release(key()?),
+                Some(&"quit") => ScriptAction::Quit,
+                _ => return Err(error("expected down, up or quit")),
+            };
+
+            events.push(ScriptEvent { poll, action });
         }
 
-        Ok(true)     
+        Ok(ScriptedInput::new(events))
     }
+}
 
-    pub fn wait_for_key(&mut self) -> Result<u8, String> {
-
-        loop {
-            // clone the old keyboard state
-            let old_pressed_keys = self.pressed_keys.clone();
+impl InputSource for ScriptedInput {
+    fn poll(&mut self) -> Result<bool, String> {
+        while let Some(event) = self.events.get(self.next_event) {
+            if event.poll > self.polls {
+                break;
+            }
 
-            // get the new state
-            match self.update_pressed_keys() {
-                Ok(true) => (),
-                Ok(false) => return Ok(0xFF),
-                Err(_) => return Err(String::new()),
+            match event.action {
+                ScriptAction::Press(key) => self.pressed_keys[key as usize] = true,
+                ScriptAction::Release(key) => self.pressed_keys[key as usize] = false,
+                ScriptAction::Quit => return Ok(false),
             };
+            self.next_event += 1;
+        }
+        self.polls += 1;
 
-            // finding a difference in the two arrays means there was a key press or release 
-            // if it was a release we keep waiting
-            for i in 0..16 {
-                if old_pressed_keys[i] == false && true == self.pressed_keys[i] {
-                    return Ok(i as u8);
-                }
-            }
+        Ok(true)
+    }
+
+    fn keys(&self) -> [bool; 16] {
+        self.pressed_keys
+    }
+}
+
+// bit N is key N
+fn keys_to_bits(keys: &[bool; 16]) -> u16 {
+    keys.iter().enumerate().fold(0, |bits, (key, pressed)| bits | ((*pressed as u16) << key))
+}
+
+fn keys_from_bits(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = (bits >> key) & 0x1 == 1;
+    }
+
+    keys
+}
+
+/// Key state latched by the core from its input source
+#[derive(Default)]
+pub struct Keypad {
+    pressed_keys: [bool; 16],
+    // state of the previous latch, used to find the keys that changed
+    previous_keys: [bool; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            pressed_keys: [false; 16],
+            previous_keys: [false; 16],
         }
-        
+    }
+
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.previous_keys = self.pressed_keys;
+        self.pressed_keys = keys;
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.pressed_keys[key as usize] = pressed;
     }
 
     pub fn is_key_pressed(&self, key: u8) -> bool {
-        self.pressed_keys[key as usize]
+        self.pressed_keys[(key & 0x0F) as usize]
+    }
+
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.put_u16(keys_to_bits(&self.pressed_keys));
+        out.put_u16(keys_to_bits(&self.previous_keys));
     }
-}
\ No newline at end of file
+
+    pub fn read_state(input: &mut StateReader) -> Result<Keypad, EmulatorError> {
+        Ok(Keypad {
+            pressed_keys: keys_from_bits(input.get_u16()?),
+            previous_keys: keys_from_bits(input.get_u16()?),
+        })
+    }
+
+    /// Returns the lowest key that went down on the last latch, if any.
+    /// A key is only reported once, even when several instructions run between two latches
+    pub fn take_newly_pressed_key(&mut self) -> Option<u8> {
+        let key = (0..16).find(|&key| self.pressed_keys[key] && !self.previous_keys[key])?;
+        self.previous_keys[key] = true;
+
+        Some(key as u8)
+    }
+}
+
+#[cfg(test)]
+#[path = "keypad_test.rs"]
+mod keypad_test;
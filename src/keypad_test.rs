@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn scripted_input_test() {
+    let script = "
+        # press 5 on the second poll and hold it for two polls
+        1 down 5
+        3 up 0x5
+        4 quit
+    ";
+    let mut input = ScriptedInput::from_script(script).unwrap();
+
+    assert!(input.poll().unwrap());
+    assert_eq!([false; 16], input.keys());
+
+    assert!(input.poll().unwrap());
+    assert!(input.keys()[5]);
+    assert!(input.poll().unwrap());
+    assert!(input.keys()[5]);
+
+    assert!(input.poll().unwrap());
+    assert!(!input.keys()[5]);
+
+    assert!(!input.poll().unwrap());
+}
+
+#[test]
+fn scripted_input_errors_test() {
+    assert_eq!("line 2 : key must be between 0 and F", ScriptedInput::from_script("1 down 1\n2 down 10").err().unwrap());
+    assert_eq!("line 1 : expected down, up or quit", ScriptedInput::from_script("1 press 1").err().unwrap());
+    assert_eq!("line 1 : expected a poll number", ScriptedInput::from_script("x down 1").err().unwrap());
+}
+
+#[test]
+fn held_key_test() {
+    let mut keypad = Keypad::new();
+    let mut input = ScriptedInput::from_script("0 down 3\n3 up 3").unwrap();
+
+    input.poll().unwrap();
+    keypad.set_keys(input.keys());
+    assert_eq!(Some(3), keypad.take_newly_pressed_key());
+
+    // the key stays pressed until it is released but is only reported as new once
+    for _ in 0..2 {
+        input.poll().unwrap();
+        keypad.set_keys(input.keys());
+        assert!(keypad.is_key_pressed(3));
+        assert_eq!(None, keypad.take_newly_pressed_key());
+    }
+
+    input.poll().unwrap();
+    keypad.set_keys(input.keys());
+    assert!(!keypad.is_key_pressed(3));
+}
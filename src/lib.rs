@@ -0,0 +1,24 @@
+pub mod cpu;
+pub mod memory;
+pub mod keypad;
+pub mod display;
+pub mod fontset;
+pub mod error;
+pub mod scheduler;
+pub mod quirks;
+pub mod savestate;
+pub mod rewind;
+pub mod instruction;
+pub mod disasm;
+pub mod asm;
+pub mod breakpoint;
+pub mod debugger;
+pub mod gdb;
+pub mod overlay;
+pub mod audio;
+pub mod trace;
+pub mod profile;
+pub mod cli;
+
+#[cfg(feature = "sdl")]
+pub mod sdl;
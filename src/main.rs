@@ -1,51 +1,343 @@
-mod cpu;
-mod keypad;
-mod display;
-mod fontset;
-
-extern crate sdl2;
-use cpu::Cpu;
+use chip8::cli::{self, Command, Options, GAMES_DIR, USAGE};
+use chip8::cpu::Cpu;
+use chip8::error::{EmulatorError, StepOutcome};
+use chip8::display::NullVideo;
+use chip8::keypad::NullInput;
+use chip8::audio::NullAudio;
+use chip8::scheduler::Scheduler;
+use chip8::trace::{TraceLevel, Tracer};
+use chip8::asm;
+use chip8::debugger::{self, DebugCommand, Debugger};
+use chip8::disasm;
+use chip8::profile::Profiler;
+use chip8::gdb::GdbStub;
 use std::process;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 
-fn get_rom_name(args: Vec<String>) -> String {
-    if args.len() == 1 {
-        return "TICTAC".to_string();
-    } 
+/// Bundled games can still be started by name, e.g. `cargo run TICTAC`
+fn resolve_rom_path(rom: &str) -> String {
+    let bundled = format!("{}/{}", GAMES_DIR, rom);
+    if !Path::new(rom).exists() && Path::new(&bundled).exists() {
+        return bundled;
+    }
 
-    args[1].clone()
+    rom.to_string()
 }
 
-fn main() {
-    
-    let args: Vec<String> = env::args().collect();
-    let rom = get_rom_name(args);
+/// Reads the whole ROM, "-" reads it from stdin
+fn read_rom(rom: &str) -> io::Result<Vec<u8>> {
+    if rom == "-" {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        return Ok(buffer);
+    }
 
-    let sdl_context = sdl2::init().unwrap();
-    let mut cpu = Cpu::new(&sdl_context);
+    fs::read(resolve_rom_path(rom))
+}
+
+/// The CPU ready to run the game with `tracer`, and the ROM it loaded
+fn setup_cpu(options: &Options, tracer: Option<Tracer>) -> Result<(Cpu, Vec<u8>), EmulatorError> {
+    let mut cpu = Cpu::new();
 
-    match cpu.initialize() {
-        Ok(_) => println!("Cpu initialized successfully!"),
-        Err(err) => println!("Cpu init failed! : {}", err),
+    cpu.set_memory_size(options.memory_size);
+    cpu.set_quirks(options.quirks);
+    cpu.set_stack_config(options.stack)?;
+    cpu.set_load_address(options.load_address);
+    cpu.set_memory_policy(options.memory_policy);
+    cpu.set_tracer(tracer);
+    if options.profile.is_some() {
+        cpu.set_profiler(Some(Profiler::new()));
+    }
+    if let Some(seed) = options.seed {
+        cpu.set_seed(seed);
     }
 
-    match cpu.load_game(format!("games/{}", rom)) {
-        Ok(()) => println!("Game loaded!"),
-        Err(err) =>  {
-            println!("Could not load game! : {}", err);
-            process::exit(1);
-        },
+    cpu.initialize();
+    if options.trace >= TraceLevel::Info {
+        println!("Cpu initialized successfully!");
+    }
+
+    let rom = read_rom(&options.rom)?;
+    cpu.load_rom(&rom)?;
+    if options.trace >= TraceLevel::Info {
+        println!("Game loaded!");
+    }
+
+    Ok((cpu, rom))
+}
+
+/// Writes the report of the profiler followed by the annotated listing of the ROM
+fn write_profile(cpu: &Cpu, rom: &[u8], path: &str) -> io::Result<()> {
+    let profiler = match cpu.get_profiler() {
+        Some(profiler) => profiler,
+        None => return Ok(()),
+    };
+    let listing = profiler.annotate(&disasm::disassemble(rom, cpu.get_load_address()));
+
+    fs::write(path, format!("{}\n{}", profiler.report(cpu.get_memory().as_slice()), listing))
+}
+
+/// Runs without any window as fast as the host allows, mostly for tests and CI
+fn run_headless(cpu: &mut Cpu, options: &Options) -> Result<(), EmulatorError> {
+    let mut scheduler = Scheduler::new(options.instructions_per_second);
+    let mut frames = 0;
+
+    while options.frames.is_none_or(|limit| frames < limit) {
+        if scheduler.run_frame(cpu, &mut NullInput, &mut NullVideo, &mut NullAudio)? == StepOutcome::Exit {
+            break;
+        }
+        frames += 1;
     }
-    
+
+    Ok(())
+}
+
+/// Reads debugger commands from stdin until quit or the end of the input
+fn run_debugger(cpu: &mut Cpu, options: &Options) -> Result<(), EmulatorError> {
+    let mut debugger = Debugger::new(options.instructions_per_second);
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    println!("Stopped at 0x{:03X}, type help for the commands", cpu.get_pc());
+
     loop {
-        match cpu.emulate_cycle() {
-            Ok(true) => continue,
-            Ok(false) => process::exit(0),
+        write!(stdout, "(chip8) ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
+        }
+        match debugger::parse_command(&line) {
+            Ok(DebugCommand::Quit) => break,
+            Ok(command) => write!(stdout, "{}", debugger.execute(cpu, command))?,
+            // an empty line just prompts again
+            Err(_) if line.trim().is_empty() => (),
+            Err(err) => eprintln!("{}", err),
+        };
+    }
+
+    Ok(())
+}
+
+/// Waits for one GDB client on localhost and serves it until it detaches
+fn run_gdb(cpu: &mut Cpu, options: &Options, port: u16) -> Result<(), EmulatorError> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on 127.0.0.1:{}, connect with `target remote :{}`", port, port);
+
+    let (stream, addr) = listener.accept()?;
+    println!("GDB connected from {}", addr);
+    GdbStub::new(cpu, stream, options.instructions_per_second).run()
+}
+
+#[cfg(feature = "sdl")]
+fn run_window(cpu: &mut Cpu, options: &Options) -> Result<(), EmulatorError> {
+    use chip8::sdl::{self, Hotkey, SdlVideo, SdlInput, SdlAudio, SdlOverlay};
+    use chip8::keypad::InputSource;
+    use chip8::overlay::DebugView;
+    use chip8::audio::AudioSink;
+    use chip8::rewind::Rewind;
+
+    let sdl_context = sdl2::init().map_err(EmulatorError::Backend)?;
+    let mut video = SdlVideo::new(&sdl_context, options.scale, options.palette).map_err(EmulatorError::Backend)?;
+    let keymap = match &options.keymap {
+        Some(path) => {
+            let names = cli::parse_keymap(&fs::read_to_string(path)?).map_err(EmulatorError::Backend)?;
+            sdl::keymap_from_names(&names).map_err(EmulatorError::Backend)?
+        },
+        None => sdl::default_keymap(),
+    };
+    let mut input = SdlInput::with_keymap(&sdl_context, keymap).map_err(EmulatorError::Backend)?;
+    let mut audio: Box<dyn AudioSink> = if options.mute {
+        Box::new(NullAudio)
+    } else {
+        Box::new(SdlAudio::new(&sdl_context, options.audio).map_err(EmulatorError::Backend)?)
+    };
+
+    let mut scheduler = Scheduler::new(options.instructions_per_second);
+    let mut rewind = Rewind::new(options.rewind_seconds);
+    // the debug overlay, opened with F9
+    let mut overlay: Option<SdlOverlay> = None;
+    let mut frames = 0;
+
+    // run until the window is closed
+    while options.frames.is_none_or(|limit| frames < limit) {
+        if input.is_rewinding() {
+            // one frame back per frame, the game stays on its oldest frame once the history is used up
+            if cpu.poll_input(&mut input)? == StepOutcome::Quit {
+                break;
+            }
+            rewind.step_back(cpu)?;
+            audio.set_beeping(false).map_err(EmulatorError::Backend)?;
+            cpu.present(&mut video)?;
+        } else {
+            rewind.record(cpu);
+            match scheduler.run_frame(cpu, &mut input, &mut video, audio.as_mut())? {
+                StepOutcome::Quit | StepOutcome::Exit => break,
+                _ => (),
+            };
+        }
+
+        // a failed save or load is reported but doesn't stop the game
+        let mut closed = false;
+        for hotkey in input.take_hotkeys() {
+            let result = match hotkey {
+                Hotkey::SaveState(slot) => {
+                    let path = cli::state_slot_path(&options.rom, slot);
+                    fs::create_dir_all(cli::STATES_DIR).map_err(EmulatorError::from)
+                        .and_then(|_| cpu.save_state_file(&path))
+                        .map(|_| format!("State saved to slot {}", slot))
+                },
+                Hotkey::LoadState(slot) => {
+                    cpu.load_state_file(cli::state_slot_path(&options.rom, slot))
+                        .map(|_| format!("State loaded from slot {}", slot))
+                },
+                Hotkey::ToggleOverlay => {
+                    overlay = match overlay {
+                        Some(_) => None,
+                        None => Some(SdlOverlay::new(&sdl_context, &video).map_err(EmulatorError::Backend)?),
+                    };
+                    continue;
+                },
+                // closing the game window quits even while the overlay is open
+                Hotkey::CloseWindow(id) => {
+                    if overlay.as_ref().is_some_and(|overlay| overlay.window_id() == id) {
+                        overlay = None;
+                    } else {
+                        closed = true;
+                    }
+                    continue;
+                },
+            };
+            match result {
+                Ok(message) => if options.trace >= TraceLevel::Info { println!("{}", message) },
+                Err(err) => eprintln!("{}", err),
+            };
+        }
+        if closed {
+            break;
+        }
+        if let Some(overlay) = overlay.as_mut() {
+            overlay.present(&DebugView::capture(cpu, input.keys())).map_err(EmulatorError::Backend)?;
+        }
+        scheduler.wait_for_next_frame();
+        frames += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_window(_cpu: &mut Cpu, _options: &Options) -> Result<(), EmulatorError> {
+    Err(EmulatorError::Backend(String::from("built without the sdl feature, only --headless is available")))
+}
+
+fn main() {
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match cli::parse_args(&args) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            process::exit(0);
+        },
+        Ok(Command::Disasm { rom, load_address }) => {
+            match read_rom(&rom) {
+                // a closed pipe, like `chip8 disasm PONG | head`, isn't an error
+                Ok(bytes) => { let _ = write!(io::stdout(), "{}", disasm::disassemble(&bytes, load_address)); },
+                Err(err) => {
+                    eprintln!("Could not read {} : {}", rom, err);
+                    process::exit(1);
+                },
+            };
+            process::exit(0);
+        },
+        Ok(Command::Asm { source, output, load_address }) => {
+            let output = output.map(PathBuf::from).unwrap_or_else(|| asm::output_path(Path::new(&source)));
+            let rom = match asm::assemble_file(&source, load_address) {
+                Ok(rom) => rom,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                },
+            };
+            if let Err(err) = fs::write(&output, &rom) {
+                eprintln!("Could not write {} : {}", output.display(), err);
+                process::exit(1);
+            }
+            println!("{} bytes written to {}", rom.len(), output.display());
+            process::exit(0);
+        },
+        Ok(Command::List) => {
+            match cli::list_games() {
+                Ok(games) => games.iter().for_each(|game| println!("{}", game)),
+                Err(err) => {
+                    eprintln!("Could not list the games in {}/ : {}", GAMES_DIR, err);
+                    process::exit(1);
+                },
+            };
+            process::exit(0);
+        },
+        Err(err) => {
+            eprintln!("error: {}\n\nRun with --help to see the available options", err);
+            process::exit(2);
+        },
+    };
+
+    // a trace file gets the bare records to be diffed, the console gets the mnemonics too
+    let tracer = match &options.trace_file {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Tracer::new(options.trace_filter, Box::new(BufWriter::new(file)), false)),
             Err(err) => {
-                println!("An error occured : {}", err);
+                eprintln!("Could not create the trace file {} : {}", path, err);
                 process::exit(1);
             },
+        },
+        None if options.trace >= TraceLevel::Trace => Some(Tracer::new(options.trace_filter, Box::new(io::stdout()), true)),
+        None => None,
+    };
+
+    let (mut cpu, rom) = match setup_cpu(&options, tracer) {
+        Ok(setup) => setup,
+        Err(err) => {
+            eprintln!("Could not load game! : {}", err);
+            process::exit(1);
+        },
+    };
+
+    let result = if let Some(port) = options.gdb_port {
+        run_gdb(&mut cpu, &options, port)
+    } else if options.debug {
+        run_debugger(&mut cpu, &options)
+    } else if options.headless {
+        run_headless(&mut cpu, &options)
+    } else {
+        run_window(&mut cpu, &options)
+    };
+
+    // process::exit skips the destructors, the trace still buffered has to be written out first
+    if let Some(mut tracer) = cpu.take_tracer() {
+        if let Err(err) = tracer.flush() {
+            eprintln!("Could not write the trace : {}", err);
+        }
+    }
+
+    // the profile is also written when the game stopped on an error, it shows how it got there
+    if let Some(path) = &options.profile {
+        match write_profile(&cpu, &rom, path) {
+            Ok(()) => if options.trace >= TraceLevel::Info { println!("Profile written to {}", path) },
+            Err(err) => eprintln!("Could not write the profile to {} : {}", path, err),
         };
     }
 
+    match result {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            eprintln!("An error occured : {}", err);
+            process::exit(1);
+        },
+    }
 }
@@ -0,0 +1,165 @@
+use crate::error::EmulatorError;
+
+use std::cell::RefCell;
+use std::ops::{Index, IndexMut};
+
+// size of the original Chip8 address space
+pub const MEMORY_SIZE: usize = 4096;
+// XO-CHIP address space, reachable with F000 NNNN
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+
+/// What happens when an instruction reads or writes past the end of memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryPolicy {
+    /// addresses wrap around at the end of memory, 0x1000 is 0x000 again
+    Wrap,
+    /// the access fails with EmulatorError::MemoryOutOfBounds
+    Fault,
+    /// the access is reported on stderr, reads return 0 and writes are dropped
+    Log,
+}
+
+/// A data access made by an instruction, recorded while the access log is on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub addr: usize,
+    // byte read, or byte written
+    pub value: u8,
+    pub write: bool,
+}
+
+/// Emulated RAM. Every access made by an opcode goes through `read` and `write` so the policy applies
+pub struct Memory {
+    bytes: Vec<u8>,
+    policy: MemoryPolicy,
+    // records the accesses for the watchpoints, off unless one is set
+    log_accesses: bool,
+    accesses: RefCell<Vec<MemoryAccess>>,
+}
+
+impl Memory {
+    pub fn new(size: usize, policy: MemoryPolicy) -> Memory {
+        Memory {
+            bytes: vec![0u8; size],
+            policy,
+            log_accesses: false,
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get_policy(&self) -> MemoryPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: MemoryPolicy) {
+        self.policy = policy;
+    }
+
+    /// Turns the access log on or off, and empties it
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.log_accesses = enabled;
+        self.accesses.get_mut().clear();
+    }
+
+    /// The accesses recorded since the log was last emptied, and empties it
+    pub fn take_accesses(&mut self) -> Vec<MemoryAccess> {
+        std::mem::take(self.accesses.get_mut())
+    }
+
+    pub fn clear(&mut self) {
+        for byte in self.bytes.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Maps `addr` onto the memory according to the policy, None when the access must be skipped
+    fn resolve(&self, addr: usize, access: &str) -> Result<Option<usize>, EmulatorError> {
+        if addr < self.bytes.len() {
+            return Ok(Some(addr));
+        }
+
+        match self.policy {
+            MemoryPolicy::Wrap => Ok(Some(addr % self.bytes.len())),
+            MemoryPolicy::Fault => Err(EmulatorError::MemoryOutOfBounds { addr }),
+            MemoryPolicy::Log => {
+                eprintln!("Memory {} out of bounds at 0x{:X}, ignored", access, addr);
+                Ok(None)
+            },
+        }
+    }
+
+    // read that is never logged, None when the policy skips it
+    fn read_unlogged(&self, addr: usize) -> Result<Option<(usize, u8)>, EmulatorError> {
+        Ok(self.resolve(addr, "read")?.map(|addr| (addr, self.bytes[addr])))
+    }
+
+    pub fn read(&self, addr: usize) -> Result<u8, EmulatorError> {
+        Ok(match self.read_unlogged(addr)? {
+            Some((addr, value)) => {
+                if self.log_accesses {
+                    self.accesses.borrow_mut().push(MemoryAccess { addr, value, write: false });
+                }
+                value
+            },
+            None => 0,
+        })
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), EmulatorError> {
+        if let Some(addr) = self.resolve(addr, "write")? {
+            if self.log_accesses {
+                self.accesses.get_mut().push(MemoryAccess { addr, value, write: true });
+            }
+            self.bytes[addr] = value;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a big endian word, both bytes go through the policy
+    pub fn read_u16(&self, addr: usize) -> Result<u16, EmulatorError> {
+        Ok(((self.read(addr)? as u16) << 8) | self.read(addr + 1)? as u16)
+    }
+
+    /// Reads an instruction word, like read_u16 but never recorded as a data access
+    pub fn fetch_u16(&self, addr: usize) -> Result<u16, EmulatorError> {
+        let byte = |addr| -> Result<u16, EmulatorError> { Ok(self.read_unlogged(addr)?.map_or(0, |(_, value)| value as u16)) };
+        Ok((byte(addr)? << 8) | byte(addr + 1)?)
+    }
+
+    pub fn write_u16(&mut self, addr: usize, value: u16) -> Result<(), EmulatorError> {
+        self.write(addr, (value >> 8) as u8)?;
+        self.write(addr + 1, value as u8)
+    }
+}
+
+/// Direct access for loaders and tools, panics when out of bounds instead of applying the policy
+impl Index<usize> for Memory {
+    type Output = u8;
+
+    fn index(&self, addr: usize) -> &u8 {
+        &self.bytes[addr]
+    }
+}
+
+impl IndexMut<usize> for Memory {
+    fn index_mut(&mut self, addr: usize) -> &mut u8 {
+        &mut self.bytes[addr]
+    }
+}
+
+#[cfg(test)]
+#[path = "memory_test.rs"]
+mod memory_test;
@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn wrap_policy_test() {
+    let mut memory = Memory::new(MEMORY_SIZE, MemoryPolicy::Wrap);
+
+    memory.write(0x1001, 0xAB).unwrap();
+    assert_eq!(0xAB, memory[0x001]);
+    assert_eq!(0xAB, memory.read(0x2001).unwrap());
+
+    memory.write_u16(0xFFF, 0x1234).unwrap();
+    assert_eq!(0x12, memory[0xFFF]);
+    assert_eq!(0x34, memory[0x000]);
+}
+
+#[test]
+fn fault_policy_test() {
+    let mut memory = Memory::new(MEMORY_SIZE, MemoryPolicy::Fault);
+
+    match memory.read_u16(0xFFF) {
+        Err(EmulatorError::MemoryOutOfBounds { addr }) => assert_eq!(0x1000, addr),
+        other => panic!("expected an out of bounds error, got {:?}", other),
+    };
+    assert!(memory.write(0x1000, 1).is_err());
+    assert!(memory.write(0xFFF, 1).is_ok());
+}
+
+#[test]
+fn log_policy_test() {
+    let mut memory = Memory::new(MEMORY_SIZE, MemoryPolicy::Log);
+    memory[0] = 0x11;
+
+    memory.write(0x1000, 0xFF).unwrap();
+    assert_eq!(0x11, memory[0]);
+    assert_eq!(0, memory.read(0x1000).unwrap());
+}
+
+#[test]
+fn access_log_test() {
+    let mut memory = Memory::new(MEMORY_SIZE, MemoryPolicy::Wrap);
+    memory.write(0x300, 0x12).unwrap();
+    assert!(memory.take_accesses().is_empty());
+
+    memory.set_access_log(true);
+    memory.write(0x1301, 0x34).unwrap();
+    assert_eq!(0x1234, memory.fetch_u16(0x300).unwrap());
+    assert_eq!(0x34, memory.read(0x301).unwrap());
+    // the addresses are the ones after the policy, the fetch isn't recorded
+    assert_eq!(vec![
+        MemoryAccess { addr: 0x301, value: 0x34, write: true },
+        MemoryAccess { addr: 0x301, value: 0x34, write: false },
+    ], memory.take_accesses());
+    assert!(memory.take_accesses().is_empty());
+}
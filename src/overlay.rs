@@ -0,0 +1,164 @@
+use crate::cpu::Cpu;
+use crate::debugger;
+use crate::fontset::FONTSET;
+
+// size of the view in characters
+pub const COLUMNS: usize = 80;
+pub const ROWS: usize = 25;
+// glyphs are 4x5 pixels like the Chip8 font
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// the right column holds the disassembly and the memory
+const RIGHT_COLUMN: usize = 28;
+const LISTING_LINES: usize = 16;
+// instructions shown before the pc
+const LISTING_CONTEXT: usize = 6;
+const MEMORY_LINES: usize = 8;
+// stack entries shown, innermost first
+const STACK_LINES: usize = 8;
+// the keys in the layout of the COSMAC VIP keypad
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+/// Pixels of a character, one row per byte from the top and the leftmost pixel in the high bit.
+/// Hex digits come from the Chip8 font, letters are upper-cased and unknown characters shown as `?`
+pub fn glyph(ch: char) -> [u8; GLYPH_HEIGHT] {
+    let ch = ch.to_ascii_uppercase();
+    if let Some(digit) = ch.to_digit(16) {
+        let start = digit as usize * GLYPH_HEIGHT;
+        let mut rows = [0u8; GLYPH_HEIGHT];
+        rows.copy_from_slice(&FONTSET[start..start + GLYPH_HEIGHT]);
+        return rows;
+    }
+
+    match ch {
+        'G' => [0xF0, 0x80, 0xB0, 0x90, 0xF0],
+        'H' => [0x90, 0x90, 0xF0, 0x90, 0x90],
+        'I' => [0xE0, 0x40, 0x40, 0x40, 0xE0],
+        'J' => [0x70, 0x20, 0x20, 0xA0, 0xE0],
+        'K' => [0x90, 0xA0, 0xC0, 0xA0, 0x90],
+        'L' => [0x80, 0x80, 0x80, 0x80, 0xF0],
+        'M' => [0x90, 0xF0, 0xF0, 0x90, 0x90],
+        'N' => [0x90, 0xD0, 0xB0, 0x90, 0x90],
+        'O' => [0x60, 0x90, 0x90, 0x90, 0x60],
+        'P' => [0xE0, 0x90, 0xE0, 0x80, 0x80],
+        'Q' => [0x60, 0x90, 0x90, 0xB0, 0x70],
+        'R' => [0xE0, 0x90, 0xE0, 0xA0, 0x90],
+        'S' => [0x70, 0x80, 0x60, 0x10, 0xE0],
+        'T' => [0xE0, 0x40, 0x40, 0x40, 0x40],
+        'U' => [0x90, 0x90, 0x90, 0x90, 0x60],
+        'V' => [0x90, 0x90, 0x90, 0xA0, 0x40],
+        'W' => [0x90, 0x90, 0xF0, 0xF0, 0x90],
+        'X' => [0x90, 0x90, 0x60, 0x90, 0x90],
+        'Y' => [0xA0, 0xA0, 0x40, 0x40, 0x40],
+        'Z' => [0xF0, 0x10, 0x60, 0x80, 0xF0],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        ',' => [0x00, 0x00, 0x00, 0x40, 0x80],
+        ':' => [0x00, 0x40, 0x00, 0x40, 0x00],
+        '-' => [0x00, 0x00, 0xE0, 0x00, 0x00],
+        '+' => [0x00, 0x40, 0xE0, 0x40, 0x00],
+        '=' => [0x00, 0xE0, 0x00, 0xE0, 0x00],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        '<' => [0x20, 0x40, 0x80, 0x40, 0x20],
+        '*' => [0x00, 0xA0, 0x40, 0xA0, 0x00],
+        '#' => [0x50, 0xF0, 0x50, 0xF0, 0x50],
+        '[' => [0x60, 0x40, 0x40, 0x40, 0x60],
+        ']' => [0x60, 0x20, 0x20, 0x20, 0x60],
+        '(' => [0x20, 0x40, 0x40, 0x40, 0x20],
+        ')' => [0x40, 0x20, 0x20, 0x20, 0x40],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xF0],
+        _ => [0xE0, 0x10, 0x60, 0x00, 0x40],
+    }
+}
+
+/// A character of the view and whether it stands out, like the pc line or the byte at I
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub highlight: bool,
+}
+
+/// Text panels showing the machine state : registers, timers, stack, keypad,
+/// the disassembly around the pc and the memory around I
+pub struct DebugView {
+    cells: Vec<Cell>,
+}
+
+impl DebugView {
+    /// The state of `cpu` with `keys` held down
+    pub fn capture(cpu: &Cpu, keys: [bool; 16]) -> DebugView {
+        let mut view = DebugView {
+            cells: vec![Cell { ch: ' ', highlight: false }; COLUMNS * ROWS],
+        };
+
+        view.write(0, 0, &format!("PC {:03X}  I {:03X}  SP {:X}", cpu.get_pc(), cpu.get_i(), cpu.get_sp()), false);
+        view.write(0, 1, &format!("DT {:02X}   ST {:02X}", cpu.get_delay_timer(), cpu.get_sound_timer()), false);
+        for row in 0..4 {
+            let registers: Vec<String> = (row * 4..row * 4 + 4).map(|index| format!("V{:X} {:02X}", index, cpu.get_register(index))).collect();
+            view.write(0, 3 + row, &registers.join("  "), false);
+        }
+
+        view.write(0, 8, "STACK", false);
+        for (depth, addr) in cpu.get_stack().iter().rev().take(STACK_LINES).enumerate() {
+            view.write(0, 9 + depth, &format!("#{} {:03X}", depth, addr), false);
+        }
+
+        view.write(0, 18, "KEYPAD", false);
+        for (row, line) in KEYPAD_LAYOUT.iter().enumerate() {
+            for (column, key) in line.iter().enumerate() {
+                view.write(column * 3, 19 + row, &format!(" {:X} ", key), keys[*key as usize]);
+            }
+        }
+
+        let start = cpu.get_pc().saturating_sub(2 * LISTING_CONTEXT);
+        for (row, line) in debugger::format_list(cpu, start, LISTING_LINES).lines().enumerate() {
+            view.write(RIGHT_COLUMN, row, line, line.starts_with("=>"));
+        }
+
+        view.write_memory(cpu);
+        view
+    }
+
+    // 16 bytes per line from the line before I, the byte at I stands out
+    fn write_memory(&mut self, cpu: &Cpu) {
+        let memory = cpu.get_memory().as_slice();
+        let i = cpu.get_i();
+        let last_line = memory.len().saturating_sub(16 * MEMORY_LINES) & !0xF;
+        let start = ((i & !0xF).saturating_sub(16)).min(last_line);
+
+        self.write(RIGHT_COLUMN, LISTING_LINES, "MEMORY", false);
+        for row in 0..MEMORY_LINES {
+            let line = start + row * 16;
+            let y = LISTING_LINES + 1 + row;
+            self.write(RIGHT_COLUMN, y, &format!("{:03X}", line), false);
+            for (offset, byte) in memory.iter().skip(line).take(16).enumerate() {
+                self.write(RIGHT_COLUMN + 5 + offset * 3, y, &format!("{:02X}", byte), line + offset == i);
+            }
+        }
+    }
+
+    // text that doesn't fit in the view is cut
+    fn write(&mut self, x: usize, y: usize, text: &str, highlight: bool) {
+        if y >= ROWS {
+            return;
+        }
+        for (offset, ch) in text.chars().enumerate().take(COLUMNS.saturating_sub(x)) {
+            self.cells[y * COLUMNS + x + offset] = Cell { ch, highlight };
+        }
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        self.cells[y * COLUMNS + x]
+    }
+
+    /// The text of a row without its trailing spaces
+    pub fn line(&self, y: usize) -> String {
+        let line: String = self.cells[y * COLUMNS..(y + 1) * COLUMNS].iter().map(|cell| cell.ch).collect();
+        line.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+#[path = "overlay_test.rs"]
+mod overlay_test;
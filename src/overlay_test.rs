@@ -0,0 +1,50 @@
+use super::*;
+
+use crate::error::StepOutcome;
+
+// V0 = 5, calls 0x208 which adds 1 to V0 and returns, then loops on itself
+const ROM: [u8; 12] = [0x60, 0x05, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+#[test]
+fn capture_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&ROM).unwrap();
+    for _ in 0..3 {
+        assert_eq!(StepOutcome::Continue, cpu.emulate_cycle().unwrap());
+    }
+    cpu.set_i(0x205);
+    cpu.set_delay_timer(0x3C);
+    let mut keys = [false; 16];
+    keys[0x5] = true;
+
+    let view = DebugView::capture(&cpu, keys);
+
+    assert_eq!("PC 20A  I 205  SP 1", view.line(0)[..RIGHT_COLUMN].trim_end());
+    assert_eq!("DT 3C   ST 00", view.line(1)[..RIGHT_COLUMN].trim_end());
+    assert_eq!("V0 06  V1 00  V2 00  V3 00", view.line(3)[..RIGHT_COLUMN].trim_end());
+    assert_eq!("#0 202", view.line(9)[..RIGHT_COLUMN].trim_end());
+    assert_eq!(" 4  5  6  D", view.line(20)[..RIGHT_COLUMN].trim_end());
+    assert!(view.cell(4, 20).highlight);
+    assert!(!view.cell(1, 20).highlight);
+
+    // the listing starts 6 instructions before the pc, the pc line stands out
+    assert_eq!("    1FE  00 00        SYS 0x000", &view.line(0)[RIGHT_COLUMN..]);
+    assert_eq!("=>  20A  00 EE        RET", &view.line(6)[RIGHT_COLUMN..]);
+    assert!(view.cell(RIGHT_COLUMN, 6).highlight);
+    assert!(!view.cell(RIGHT_COLUMN, 5).highlight);
+
+    assert_eq!("MEMORY", &view.line(16)[RIGHT_COLUMN..]);
+    assert_eq!("1F0  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00", &view.line(17)[RIGHT_COLUMN..]);
+    assert_eq!("200  60 05 22 08 12 04 00 00 70 01 00 EE 00 00 00 00", &view.line(18)[RIGHT_COLUMN..]);
+    let byte_at_i = RIGHT_COLUMN + 5 + 5 * 3;
+    assert!(view.cell(byte_at_i, 18).highlight && view.cell(byte_at_i + 1, 18).highlight);
+    assert!(!view.cell(byte_at_i - 3, 18).highlight);
+}
+
+#[test]
+fn glyph_test() {
+    assert_eq!([0xF0, 0x90, 0xF0, 0x90, 0x90], glyph('a'));
+    assert_eq!([0x80, 0x80, 0x80, 0x80, 0xF0], glyph('L'));
+    assert_eq!(glyph('?'), glyph('~'));
+}
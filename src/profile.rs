@@ -0,0 +1,164 @@
+use crate::disasm::Disassembly;
+use crate::instruction;
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// addresses listed in the hot spots of the report
+const HOT_SPOTS: usize = 20;
+
+/// Time spent in a subroutine, in executed instructions.
+/// A recursive subroutine counts the time of its inner calls again in `cycles`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Subroutine {
+    pub calls: u64,
+    // from the CALL to the RET, subroutines it calls included
+    pub cycles: u64,
+    // the instructions of this subroutine only
+    pub self_cycles: u64,
+}
+
+// a call that hasn't returned yet
+struct Call {
+    entry: usize,
+    // total when the call was made
+    start: u64,
+    // cycles of the subroutines it called, already returned
+    callees: u64,
+}
+
+/// Counts of the executed instructions, by address, by opcode class and by subroutine.
+/// A cycle is one executed instruction, the unit --ips is given in
+pub struct Profiler {
+    counts: Vec<u64>,
+    classes: [u64; 16],
+    total: u64,
+    // innermost last
+    calls: Vec<Call>,
+    // by entry address
+    subroutines: BTreeMap<usize, Subroutine>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            counts: vec![0; XO_CHIP_MEMORY_SIZE],
+            classes: [0; 16],
+            total: 0,
+            calls: Vec::new(),
+            subroutines: BTreeMap::new(),
+        }
+    }
+
+    /// Counts the instruction about to run at `pc`
+    pub fn record(&mut self, pc: usize, opcode: u16) {
+        if let Some(count) = self.counts.get_mut(pc) {
+            *count += 1;
+        }
+        self.classes[(opcode >> 12) as usize] += 1;
+        self.total += 1;
+    }
+
+    /// A CALL to `entry` was made, the following instructions belong to it until the matching RET
+    pub fn enter(&mut self, entry: usize) {
+        self.calls.push(Call { entry, start: self.total, callees: 0 });
+    }
+
+    /// The innermost subroutine returned, a RET without a call seen before is ignored
+    pub fn leave(&mut self) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let cycles = self.total - call.start;
+
+        let subroutine = self.subroutines.entry(call.entry).or_default();
+        subroutine.calls += 1;
+        subroutine.cycles += cycles;
+        subroutine.self_cycles += cycles - call.callees;
+        if let Some(caller) = self.calls.last_mut() {
+            caller.callees += cycles;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Executions of the instruction at `addr`
+    pub fn count(&self, addr: usize) -> u64 {
+        self.counts.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Executed instructions whose opcode starts with the hex digit `class`
+    pub fn class_count(&self, class: usize) -> u64 {
+        self.classes[class & 0xF]
+    }
+
+    /// Calls that returned, the ones still running aren't counted
+    pub fn subroutine(&self, entry: usize) -> Option<Subroutine> {
+        self.subroutines.get(&entry).copied()
+    }
+
+    /// Totals by opcode class, the most executed addresses with their instruction in `memory`,
+    /// and the subroutines from the most expensive
+    pub fn report(&self, memory: &[u8]) -> String {
+        let mut out = format!("{} instructions executed\n", self.total);
+
+        let _ = writeln!(out, "\nOpcode classes");
+        for (class, count) in self.classes.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let _ = writeln!(out, "  {:X}xxx  {:>10}  {:>5.1}%", class, count, percent(*count, self.total));
+        }
+
+        let _ = writeln!(out, "\nHot spots");
+        let mut hot_spots: Vec<(usize, u64)> = self.counts.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+        // the most executed first, then by address
+        hot_spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (addr, count) in hot_spots.into_iter().take(HOT_SPOTS) {
+            let text = memory.get(addr..).and_then(instruction::decode).map_or_else(|| String::from("???"), |instruction| instruction.to_string());
+            let _ = writeln!(out, "  {:03X}  {:>10}  {:>5.1}%  {}", addr, count, percent(count, self.total), text);
+        }
+
+        let _ = writeln!(out, "\nSubroutines");
+        if self.subroutines.is_empty() {
+            let _ = writeln!(out, "  no subroutine returned");
+        } else {
+            let _ = writeln!(out, "  entry       calls      cycles        self    per call");
+            let mut subroutines: Vec<(&usize, &Subroutine)> = self.subroutines.iter().collect();
+            subroutines.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+            for (entry, subroutine) in subroutines {
+                let _ = writeln!(out, "  {:03X}    {:>10}  {:>10}  {:>10}  {:>10.1}", entry, subroutine.calls, subroutine.cycles,
+                    subroutine.self_cycles, subroutine.cycles as f64 / subroutine.calls as f64);
+            }
+        }
+
+        out
+    }
+
+    /// The listing of `disassembly` with the executions of each line and their share of the total in front
+    pub fn annotate(&self, disassembly: &Disassembly) -> String {
+        disassembly.annotated(&|addr, size| {
+            let count: u64 = (addr..addr + size).map(|addr| self.count(addr)).sum();
+            if count == 0 {
+                " ".repeat(19)
+            } else {
+                format!("{:>10} {:>6.1}% ", count, percent(count, self.total))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "profile_test.rs"]
+mod profile_test;
@@ -0,0 +1,51 @@
+use super::*;
+
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::error::StepOutcome;
+
+// calls 0x208 twice then exits, 0x208 calls 0x20E
+const ROM: [u8; 18] = [0x22, 0x08, 0x22, 0x08, 0x00, 0xFD, 0x00, 0x00, 0x60, 0x01, 0x22, 0x0E, 0x00, 0xEE, 0x70, 0x01, 0x00, 0xEE];
+
+fn profile() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_rom(&ROM).unwrap();
+    cpu.set_profiler(Some(Profiler::new()));
+    while cpu.emulate_cycle().unwrap() != StepOutcome::Exit {}
+    cpu
+}
+
+#[test]
+fn counts_test() {
+    let cpu = profile();
+    let profiler = cpu.get_profiler().unwrap();
+
+    assert_eq!(13, profiler.total());
+    assert_eq!(1, profiler.count(0x200));
+    assert_eq!(2, profiler.count(0x208));
+    assert_eq!(0, profiler.count(0x206));
+    assert_eq!(4, profiler.class_count(0x2));
+    assert_eq!(5, profiler.class_count(0x0));
+
+    // the CALL counts in the caller, the RET in the subroutine
+    assert_eq!(Some(Subroutine { calls: 2, cycles: 10, self_cycles: 6 }), profiler.subroutine(0x208));
+    assert_eq!(Some(Subroutine { calls: 2, cycles: 4, self_cycles: 4 }), profiler.subroutine(0x20E));
+    assert_eq!(None, profiler.subroutine(0x200));
+}
+
+#[test]
+fn report_test() {
+    let cpu = profile();
+    let profiler = cpu.get_profiler().unwrap();
+
+    let report = profiler.report(cpu.get_memory().as_slice());
+    assert!(report.starts_with("13 instructions executed\n\nOpcode classes\n  0xxx           5   38.5%\n  2xxx           4   30.8%\n"));
+    assert!(report.contains("\nHot spots\n  208           2   15.4%  LD V0, 0x01\n"));
+    assert!(report.contains("  208             2          10           6         5.0\n  20E             2           4           4         2.0\n"));
+
+    let listing = profiler.annotate(&disasm::disassemble(&ROM, 0x200));
+    assert!(listing.starts_with("         1    7.7%     CALL sub_208"));
+    assert!(listing.contains(&format!("\n{}sub_208:\n         2   15.4%     LD V0, 0x01", " ".repeat(19))));
+    assert!(listing.contains(&format!("\n{}    DB 0x00, 0x00", " ".repeat(19))));
+}
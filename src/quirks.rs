@@ -0,0 +1,111 @@
+/// Behaviours that differ between the CHIP-8 interpreters. Games usually rely on the ones of the interpreter they were written for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift v[Y] into v[X] instead of shifting v[X] in place
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing after the last register stored or loaded
+    pub load_store_increments_i: bool,
+    /// BNNN jumps to NNN + v[X] (X being the top nibble of NNN) instead of NNN + v[0]
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 set v[F] to 0
+    pub vf_reset: bool,
+    /// sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60Hz frame before the CPU goes on, at most one sprite is drawn per frame
+    pub display_wait: bool,
+    /// FX0A completes as soon as a key goes down instead of waiting for its release
+    pub key_wait_on_press: bool,
+    /// FX1E sets v[F] when I goes past 0xFFF, like the Amiga interpreter, instead of leaving v[F] alone
+    pub i_overflow_sets_vf: bool,
+}
+
+// names of the presets, as accepted by Quirks::from_name
+pub const PROFILES: [&str; 5] = ["cosmac-vip", "chip-48", "super-chip", "xo-chip", "modern"];
+
+impl Quirks {
+    /// The original interpreter of the RCA COSMAC VIP
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            key_wait_on_press: false,
+            i_overflow_sets_vf: false,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub fn chip_48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            key_wait_on_press: false,
+            i_overflow_sets_vf: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            key_wait_on_press: true,
+            ..Quirks::chip_48()
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            load_store_increments_i: true,
+            // I addresses the whole 64 KiB so going past 0xFFF is no overflow
+            i_overflow_sets_vf: false,
+            ..Quirks::modern()
+        }
+    }
+
+    /// What most games found online expect, and what this emulator always did
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+            key_wait_on_press: false,
+            i_overflow_sets_vf: true,
+        }
+    }
+
+    /// One bit per quirk, in the order of the fields. Used by the save states
+    pub fn to_bits(&self) -> u8 {
+        [self.shift_uses_vy, self.load_store_increments_i, self.jump_uses_vx, self.vf_reset,
+         self.clip_sprites, self.display_wait, self.key_wait_on_press, self.i_overflow_sets_vf]
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (index, quirk)| bits | ((*quirk as u8) << index))
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip-48" => Some(Quirks::chip_48()),
+            "super-chip" => Some(Quirks::super_chip()),
+            "xo-chip" => Some(Quirks::xo_chip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::modern()
+    }
+}
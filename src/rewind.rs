@@ -0,0 +1,100 @@
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::scheduler::TIMER_HZ;
+
+use std::collections::VecDeque;
+
+// seconds of history kept by default
+pub const DEFAULT_REWIND_SECONDS: u32 = 30;
+// most memory taken by the history, whatever its length in seconds
+pub const DEFAULT_REWIND_BYTES: usize = 64 * 1024 * 1024;
+
+/// Ring buffer of save states taken once per frame, stepping back restores them newest first.
+/// A state is about 12 KiB, mostly 4 KiB of memory and the 8 KiB XO-CHIP display, so 30 seconds take 21 MiB.
+/// With the 64 KiB of XO-CHIP memory a state grows to about 72 KiB and 30 seconds would take 127 MiB,
+/// so the history is also capped in bytes and gets shorter instead
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    // most snapshots kept, the oldest ones are dropped first
+    capacity: usize,
+    // size of the snapshots kept and the most they may take
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl Rewind {
+    /// Keeps the last `seconds` of emulation within DEFAULT_REWIND_BYTES, 0 disables the rewind
+    pub fn new(seconds: u32) -> Rewind {
+        Rewind::with_max_bytes(seconds, DEFAULT_REWIND_BYTES)
+    }
+
+    /// Keeps the last `seconds` of emulation, fewer when the snapshots would take more than `max_bytes`
+    pub fn with_max_bytes(seconds: u32, max_bytes: usize) -> Rewind {
+        let capacity = (seconds * TIMER_HZ) as usize;
+        Rewind {
+            snapshots: VecDeque::new(),
+            capacity,
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Memory taken by the snapshots kept
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.bytes = 0;
+    }
+
+    /// Records the state of the CPU, to be called once per frame
+    pub fn record(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let snapshot = cpu.save_state();
+        // the oldest snapshots make room once the ring is full
+        while self.snapshots.len() == self.capacity || (!self.snapshots.is_empty() && self.bytes + snapshot.len() > self.max_bytes) {
+            self.drop_oldest();
+        }
+        self.bytes += snapshot.len();
+        self.snapshots.push_back(snapshot);
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(snapshot) = self.snapshots.pop_front() {
+            self.bytes -= snapshot.len();
+        }
+    }
+
+    /// Restores the previous frame. Returns false when there is no history left
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, EmulatorError> {
+        match self.snapshots.pop_back() {
+            Some(snapshot) => {
+                self.bytes -= snapshot.len();
+                cpu.load_state(&snapshot)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "rewind_test.rs"]
+mod rewind_test;
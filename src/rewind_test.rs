@@ -0,0 +1,60 @@
+use super::*;
+use crate::audio::NullAudio;
+use crate::display::NullVideo;
+use crate::keypad::NullInput;
+use crate::scheduler::Scheduler;
+
+#[test]
+fn step_back_test() {
+    let mut cpu = Cpu::new();
+    let mut scheduler = Scheduler::new(TIMER_HZ);
+    let mut rewind = Rewind::new(1);
+    cpu.initialize();
+    // 7001 : v[0] += 1 then 1200 : loop, at one instruction per frame v[0] goes up every other frame
+    cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+    for _ in 0..100 {
+        rewind.record(&cpu);
+        scheduler.run_frame(&mut cpu, &mut NullInput, &mut NullVideo, &mut NullAudio).unwrap();
+    }
+    assert_eq!(TIMER_HZ as usize, rewind.len());
+
+    assert!(rewind.step_back(&mut cpu).unwrap());
+    let after_one = cpu.get_register(0x0);
+    assert!(rewind.step_back(&mut cpu).unwrap());
+    assert!(cpu.get_register(0x0) < after_one);
+
+    while rewind.step_back(&mut cpu).unwrap() {}
+    assert!(rewind.is_empty());
+    // only the last second was kept
+    assert_eq!(20, cpu.get_register(0x0));
+}
+
+#[test]
+fn disabled_test() {
+    let mut cpu = Cpu::new();
+    let mut rewind = Rewind::new(0);
+
+    rewind.record(&cpu);
+    assert!(rewind.is_empty());
+    assert!(!rewind.step_back(&mut cpu).unwrap());
+}
+
+#[test]
+fn max_bytes_test() {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    let size = cpu.save_state().len();
+    let mut rewind = Rewind::with_max_bytes(1, 3 * size + size / 2);
+
+    for value in 0..10 {
+        cpu.set_register(0x0, value);
+        rewind.record(&cpu);
+    }
+    assert_eq!(3, rewind.len());
+    assert_eq!(3 * size, rewind.bytes());
+
+    while rewind.step_back(&mut cpu).unwrap() {}
+    assert_eq!(7, cpu.get_register(0x0));
+    assert_eq!(0, rewind.bytes());
+}
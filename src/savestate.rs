@@ -0,0 +1,167 @@
+use crate::error::EmulatorError;
+
+// first bytes of every save state file
+pub const SAVESTATE_MAGIC: [u8; 4] = *b"C8ST";
+// bumped whenever the layout changes, older states are rejected
+pub const SAVESTATE_VERSION: u16 = 2;
+// version 1 didn't have the FX1E overflow quirk in its quirk bits
+const QUIRKS_WITHOUT_I_OVERFLOW_VERSION: u16 = 1;
+
+/// Identifies the ROM a save state was taken with (64 bits FNV-1a)
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in rom {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+
+    hash
+}
+
+/// Header written before the machine state, checked before anything is restored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SaveStateHeader {
+    pub version: u16,
+    pub rom_hash: u64,
+    // Quirks::to_bits of the profile the state was taken with
+    pub quirks: u8,
+    pub memory_size: usize,
+}
+
+impl SaveStateHeader {
+    pub fn write(&self, out: &mut StateWriter) {
+        out.put_bytes(&SAVESTATE_MAGIC);
+        out.put_u16(self.version);
+        out.put_u64(self.rom_hash);
+        out.put_u8(self.quirks);
+        out.put_u32(self.memory_size as u32);
+    }
+
+    /// Reads the header, failing if the data isn't a save state of the supported version
+    pub fn read(input: &mut StateReader) -> Result<SaveStateHeader, EmulatorError> {
+        if input.get_bytes(4)? != SAVESTATE_MAGIC {
+            return Err(EmulatorError::InvalidSaveState(String::from("not a save state")));
+        }
+
+        let version = input.get_u16()?;
+        if version == QUIRKS_WITHOUT_I_OVERFLOW_VERSION {
+            return Err(EmulatorError::IncompatibleSaveState(format!("version {} states use an older quirk layout, expected version {}", version, SAVESTATE_VERSION)));
+        }
+        if version != SAVESTATE_VERSION {
+            return Err(EmulatorError::InvalidSaveState(format!("version {} is not supported, expected {}", version, SAVESTATE_VERSION)));
+        }
+
+        Ok(SaveStateHeader {
+            version,
+            rom_hash: input.get_u64()?,
+            quirks: input.get_u8()?,
+            memory_size: input.get_u32()? as usize,
+        })
+    }
+}
+
+/// Little endian encoder of the save state fields
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: Vec::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Decoder matching StateWriter, every read fails cleanly on truncated data
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            position: 0,
+        }
+    }
+
+    /// Fails if anything is left after the last field
+    pub fn finish(&self) -> Result<(), EmulatorError> {
+        if self.position != self.data.len() {
+            return Err(EmulatorError::InvalidSaveState(format!("{} unexpected bytes at the end", self.data.len() - self.position)));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_bytes(&mut self, count: usize) -> Result<&'a [u8], EmulatorError> {
+        if self.data.len() - self.position < count {
+            return Err(EmulatorError::InvalidSaveState(String::from("truncated")));
+        }
+
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, EmulatorError> {
+        Ok(self.get_u8()? != 0)
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, EmulatorError> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.get_bytes(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, EmulatorError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.get_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, EmulatorError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.get_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+#[path = "savestate_test.rs"]
+mod savestate_test;
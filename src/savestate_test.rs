@@ -0,0 +1,47 @@
+use super::*;
+
+#[test]
+fn writer_reader_test() {
+    let mut out = StateWriter::new();
+    out.put_u8(0x12);
+    out.put_bool(true);
+    out.put_u16(0x3456);
+    out.put_u32(0x789A_BCDE);
+    out.put_u64(0x0123_4567_89AB_CDEF);
+    let data = out.into_bytes();
+    assert_eq!(16, data.len());
+
+    let mut input = StateReader::new(&data);
+    assert_eq!(0x12, input.get_u8().unwrap());
+    assert!(input.get_bool().unwrap());
+    assert_eq!(0x3456, input.get_u16().unwrap());
+    assert_eq!(0x789A_BCDE, input.get_u32().unwrap());
+    assert_eq!(0x0123_4567_89AB_CDEF, input.get_u64().unwrap());
+    input.finish().unwrap();
+    assert!(matches!(input.get_u8(), Err(EmulatorError::InvalidSaveState(_))));
+}
+
+#[test]
+fn header_test() {
+    let header = SaveStateHeader { version: SAVESTATE_VERSION, rom_hash: rom_hash(b"PONG"), quirks: 0x5, memory_size: 4096 };
+    let mut out = StateWriter::new();
+    header.write(&mut out);
+    let mut data = out.into_bytes();
+    assert_eq!(header, SaveStateHeader::read(&mut StateReader::new(&data)).unwrap());
+
+    data[4] = 0x01;
+    match SaveStateHeader::read(&mut StateReader::new(&data)) {
+        Err(EmulatorError::IncompatibleSaveState(reason)) => assert!(reason.starts_with("version 1 states use an older quirk layout")),
+        other => panic!("expected an incompatible version, got {:?}", other),
+    };
+
+    data[4] = 0xFF;
+    match SaveStateHeader::read(&mut StateReader::new(&data)) {
+        Err(EmulatorError::InvalidSaveState(reason)) => assert!(reason.starts_with("version 255")),
+        other => panic!("expected an invalid version, got {:?}", other),
+    };
+
+    data[0] = b'X';
+    assert!(matches!(SaveStateHeader::read(&mut StateReader::new(&data)), Err(EmulatorError::InvalidSaveState(_))));
+    assert_ne!(rom_hash(b"PONG"), rom_hash(b"BRIX"));
+}
@@ -0,0 +1,97 @@
+use crate::audio::AudioSink;
+use crate::cpu::Cpu;
+use crate::error::{EmulatorError, StepOutcome};
+use crate::display::VideoSink;
+use crate::keypad::InputSource;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+// the delay and sound timers count down at 60Hz
+pub const TIMER_HZ: u32 = 60;
+// default instructions per second, close to what most games expect
+pub const DEFAULT_IPS: u32 = 700;
+// how many frames we may fall behind before giving up on catching up
+const MAX_FRAME_LAG: u32 = 5;
+
+/// Runs the CPU at a fixed instruction rate and ticks the timers at exactly 60Hz.
+/// Each frame polls the input once, executes its share of instructions, ticks the timers,
+/// updates the beeper and presents the frame
+pub struct Scheduler {
+    instructions_per_second: u32,
+    // instructions owed to the next frames when the rate isn't a multiple of 60, in 1/60 units
+    instruction_credit: u32,
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_second: u32) -> Scheduler {
+        Scheduler {
+            instructions_per_second,
+            instruction_credit: 0,
+            frame_duration: Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64),
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn get_instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+        self.instruction_credit = 0;
+    }
+
+    /// Number of instructions to run in the next frame.
+    /// The remainder of instructions_per_second / 60 is spread over the frames so a second runs exactly the configured rate
+    pub fn instructions_for_frame(&mut self) -> u32 {
+        self.instruction_credit += self.instructions_per_second;
+        let instructions = self.instruction_credit / TIMER_HZ;
+        self.instruction_credit %= TIMER_HZ;
+
+        instructions
+    }
+
+    /// Emulates one 60Hz frame. Returns StepOutcome::Quit when the input source asked to quit,
+    /// StepOutcome::Exit when the program ended with 00FD and StepOutcome::Break when a breakpoint cut the frame short
+    pub fn run_frame(&mut self, cpu: &mut Cpu, input: &mut dyn InputSource, video: &mut dyn VideoSink, audio: &mut dyn AudioSink) -> Result<StepOutcome, EmulatorError> {
+        if cpu.poll_input(input)? == StepOutcome::Quit {
+            return Ok(StepOutcome::Quit);
+        }
+
+        let mut outcome = StepOutcome::Continue;
+        for _ in 0..self.instructions_for_frame() {
+            let step = cpu.emulate_cycle()?;
+            if matches!(step, StepOutcome::Exit | StepOutcome::Break(_)) {
+                outcome = step;
+                break;
+            }
+        }
+
+        cpu.tick_timers();
+        audio.set_pattern(cpu.get_audio_pattern()).map_err(EmulatorError::Backend)?;
+        audio.set_beeping(cpu.get_sound_timer() > 0).map_err(EmulatorError::Backend)?;
+        cpu.present(video)?;
+
+        Ok(outcome)
+    }
+
+    /// Sleeps until the start of the next frame.
+    /// If the host is too slow to keep up the schedule is reset instead of running frames back to back
+    pub fn wait_for_next_frame(&mut self) {
+        self.next_frame += self.frame_duration;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration * MAX_FRAME_LAG {
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "scheduler_test.rs"]
+mod scheduler_test;
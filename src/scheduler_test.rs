@@ -0,0 +1,62 @@
+use super::*;
+use crate::display::{MemoryVideo, NullVideo};
+use crate::keypad::NullInput;
+use crate::audio::NullAudio;
+
+#[derive(Default)]
+struct BeepLog {
+    states: Vec<bool>,
+}
+
+impl AudioSink for BeepLog {
+    fn set_beeping(&mut self, beeping: bool) -> Result<(), String> {
+        self.states.push(beeping);
+        Ok(())
+    }
+}
+
+#[test]
+fn instructions_per_frame_test() {
+    let mut scheduler = Scheduler::new(700);
+
+    let frames: Vec<u32> = (0..TIMER_HZ).map(|_| scheduler.instructions_for_frame()).collect();
+    assert_eq!(700, frames.iter().sum::<u32>());
+    assert!(frames.iter().all(|&instructions| instructions == 11 || instructions == 12));
+
+    scheduler.set_instructions_per_second(600);
+    assert_eq!(10, scheduler.instructions_for_frame());
+}
+
+#[test]
+fn run_frame_ticks_timers_once_test() {
+    let mut cpu = Cpu::new();
+    let mut scheduler = Scheduler::new(600);
+    let mut video = MemoryVideo::new();
+
+    cpu.initialize();
+    // 6005 : v[0] = 5 then F015 : delay_timer = v[0], then loop forever on 1204
+    cpu.load_rom(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+
+    assert_eq!(StepOutcome::Continue, scheduler.run_frame(&mut cpu, &mut NullInput, &mut video, &mut NullAudio).unwrap());
+    assert_eq!(4, cpu.get_delay_timer());
+    assert_eq!(1, video.frames().len());
+
+    assert_eq!(StepOutcome::Continue, scheduler.run_frame(&mut cpu, &mut NullInput, &mut video, &mut NullAudio).unwrap());
+    assert_eq!(3, cpu.get_delay_timer());
+}
+
+#[test]
+fn beep_follows_sound_timer_test() {
+    let mut cpu = Cpu::new();
+    let mut scheduler = Scheduler::new(60);
+    let mut audio = BeepLog::default();
+
+    cpu.initialize();
+    // 6002 : v[0] = 2 then F018 : sound_timer = v[0], then loop forever on 1204
+    cpu.load_rom(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+
+    for _ in 0..4 {
+        scheduler.run_frame(&mut cpu, &mut NullInput, &mut NullVideo, &mut audio).unwrap();
+    }
+    assert_eq!(vec![false, true, false, false], audio.states);
+}
@@ -0,0 +1,314 @@
+use crate::audio::{AudioConfig, AudioPattern, AudioSink, Tone};
+use crate::display::{Display, VideoSink, Rgb, WIDTH, HEIGHT};
+use crate::keypad::InputSource;
+use crate::overlay::{self, DebugView, COLUMNS, ROWS, GLYPH_WIDTH, GLYPH_HEIGHT};
+
+use sdl2::Sdl;
+use sdl2::EventPump;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+/// Renders the frames of the core into an SDL window
+pub struct SdlVideo {
+    canvas: Canvas<Window>,
+    // size of a Chip8 pixel in window pixels
+    scale: u32,
+    // colour of each pixel value, see DEFAULT_PALETTE
+    palette: [Color; 4],
+}
+
+fn to_sdl_color(color: Rgb) -> Color {
+    Color::RGB(color.0, color.1, color.2)
+}
+
+impl SdlVideo {
+    pub fn new(sdl_context: &Sdl, scale: u32, palette: [Rgb; 4]) -> Result<SdlVideo, String> {
+        let video_subsystem = sdl_context.video()?;
+        let window = video_subsystem.window("Chip8", WIDTH * scale, HEIGHT * scale)
+                                    .position_centered()
+                                    .build()
+                                    .map_err(|err| err.to_string())?;
+        let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+
+        canvas.set_draw_color(to_sdl_color(palette[0]));
+        canvas.clear();
+        canvas.present();
+
+        Ok(SdlVideo {
+            canvas,
+            scale,
+            palette: [to_sdl_color(palette[0]), to_sdl_color(palette[1]), to_sdl_color(palette[2]), to_sdl_color(palette[3])],
+        })
+    }
+}
+
+impl VideoSink for SdlVideo {
+    fn present(&mut self, frame: &Display) -> Result<(), String> {
+        let (window_width, window_height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        for y in 0..frame.height() {
+            for x in 0..frame.width() {
+                self.canvas.set_draw_color(self.palette[(frame.pixel(x, y) & 0x3) as usize]);
+
+                // each pixel spans from its edge to the next one's so the frame fills the window whatever the resolution,
+                // with odd scales the high resolution pixels alternate between two sizes
+                let (left, right) = (x * window_width / frame.width(), (x + 1) * window_width / frame.width());
+                let (top, bottom) = (y * window_height / frame.height(), (y + 1) * window_height / frame.height());
+                if right > left && bottom > top {
+                    self.canvas.fill_rect(Rect::new(left as i32, top as i32, right - left, bottom - top))?;
+                }
+            }
+        }
+
+        self.canvas.present();
+
+        Ok(())
+    }
+}
+
+// an overlay character is its glyph drawn twice as big, with a pixel of spacing on the right and 2 below
+const OVERLAY_SCALE: u32 = 2;
+const OVERLAY_CELL_WIDTH: u32 = (GLYPH_WIDTH as u32 + 1) * OVERLAY_SCALE;
+const OVERLAY_CELL_HEIGHT: u32 = (GLYPH_HEIGHT as u32 + 2) * OVERLAY_SCALE;
+const OVERLAY_BACKGROUND: Rgb = Rgb(0x10, 0x10, 0x10);
+const OVERLAY_TEXT: Rgb = Rgb(0xD0, 0xD0, 0xD0);
+// behind the pc line, the byte at I and the held keys
+const OVERLAY_HIGHLIGHT: Rgb = Rgb(0x20, 0x50, 0xA0);
+
+/// Second window showing the state of the machine next to the game, see overlay::DebugView
+pub struct SdlOverlay {
+    canvas: Canvas<Window>,
+}
+
+impl SdlOverlay {
+    /// Opens the window to the right of the game window
+    pub fn new(sdl_context: &Sdl, beside: &SdlVideo) -> Result<SdlOverlay, String> {
+        let video_subsystem = sdl_context.video()?;
+        let (x, y) = beside.canvas.window().position();
+        let (width, _) = beside.canvas.window().size();
+        let window = video_subsystem.window("Chip8 debug", COLUMNS as u32 * OVERLAY_CELL_WIDTH, ROWS as u32 * OVERLAY_CELL_HEIGHT)
+                                    .position(x + width as i32, y)
+                                    .build()
+                                    .map_err(|err| err.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+
+        Ok(SdlOverlay {
+            canvas,
+        })
+    }
+
+    /// Id of the window in the SDL events, see Hotkey::CloseWindow
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn present(&mut self, view: &DebugView) -> Result<(), String> {
+        self.canvas.set_draw_color(to_sdl_color(OVERLAY_BACKGROUND));
+        self.canvas.clear();
+
+        for y in 0..ROWS {
+            for x in 0..COLUMNS {
+                let cell = view.cell(x, y);
+                let left = (x as u32 * OVERLAY_CELL_WIDTH) as i32;
+                let top = (y as u32 * OVERLAY_CELL_HEIGHT) as i32;
+                if cell.highlight {
+                    self.canvas.set_draw_color(to_sdl_color(OVERLAY_HIGHLIGHT));
+                    self.canvas.fill_rect(Rect::new(left, top, OVERLAY_CELL_WIDTH, OVERLAY_CELL_HEIGHT))?;
+                }
+
+                self.canvas.set_draw_color(to_sdl_color(OVERLAY_TEXT));
+                for (row, bits) in overlay::glyph(cell.ch).iter().enumerate() {
+                    for column in 0..GLYPH_WIDTH {
+                        if bits & (0x80 >> column) != 0 {
+                            let pixel = Rect::new(left + (column as u32 * OVERLAY_SCALE) as i32, top + ((row as u32 + 1) * OVERLAY_SCALE) as i32, OVERLAY_SCALE, OVERLAY_SCALE);
+                            self.canvas.fill_rect(pixel)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.canvas.present();
+
+        Ok(())
+    }
+}
+
+struct ToneCallback {
+    tone: Tone,
+}
+
+impl AudioCallback for ToneCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.tone.fill(out);
+    }
+}
+
+/// Plays the beep through the SDL audio device.
+/// The device keeps running while silent so starting and stopping the tone only moves its envelope
+pub struct SdlAudio {
+    device: AudioDevice<ToneCallback>,
+}
+
+impl SdlAudio {
+    pub fn new(sdl_context: &Sdl, config: AudioConfig) -> Result<SdlAudio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = audio_subsystem.open_playback(None, &desired, |spec| {
+            ToneCallback {
+                tone: Tone::new(config, spec.freq as u32),
+            }
+        })?;
+        device.resume();
+
+        Ok(SdlAudio {
+            device,
+        })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_beeping(&mut self, beeping: bool) -> Result<(), String> {
+        self.device.lock().tone.set_beeping(beeping);
+        Ok(())
+    }
+
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>) -> Result<(), String> {
+        self.device.lock().tone.set_pattern(pattern);
+        Ok(())
+    }
+}
+
+/// Emulator commands bound to the function keys, they never reach the core
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    /// Shift + F1..F8 : saves the state to a slot
+    SaveState(u8),
+    /// F1..F8 : restores the state of a slot
+    LoadState(u8),
+    /// F9 : opens or closes the debug overlay
+    ToggleOverlay,
+    /// the close button of a window was clicked, with the id of the window.
+    /// Closing the last window quits instead
+    CloseWindow(u32),
+}
+
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    let slots = [Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8];
+    slots.iter().position(|slot| *slot == keycode).map(|slot| slot as u8 + 1)
+}
+
+/// Feeds the keyboard state of the SDL window to the core
+pub struct SdlInput {
+    event_pump: EventPump,
+    keymap: Vec<(Keycode, u8)>,
+    pressed_keys: [bool; 16],
+    // hotkeys pressed since the last take_hotkeys
+    hotkeys: Vec<Hotkey>,
+    // Backspace is held
+    rewinding: bool,
+}
+
+impl SdlInput {
+    pub fn new(sdl_context: &Sdl) -> Result<SdlInput, String> {
+        SdlInput::with_keymap(sdl_context, default_keymap())
+    }
+
+    pub fn with_keymap(sdl_context: &Sdl, keymap: Vec<(Keycode, u8)>) -> Result<SdlInput, String> {
+        Ok(SdlInput {
+            event_pump: sdl_context.event_pump()?,
+            keymap,
+            pressed_keys: [false; 16],
+            hotkeys: Vec::new(),
+            rewinding: false,
+        })
+    }
+
+    /// Whether the rewind key (Backspace) is held, the game runs backwards while it is
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    /// Hotkeys pressed since the last call, in order
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn map_keycode(&self, keycode: Keycode) -> Option<u8> {
+        self.keymap.iter().find(|(mapped, _)| *mapped == keycode).map(|(_, key)| *key)
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Result<bool, String> {
+
+        // keys stay down until their KeyUp event arrives, auto-repeated KeyDown events change nothing
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if state_slot(keycode).is_some() => {
+                    let slot = state_slot(keycode).unwrap_or(1);
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        self.hotkeys.push(Hotkey::SaveState(slot));
+                    } else {
+                        self.hotkeys.push(Hotkey::LoadState(slot));
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => self.hotkeys.push(Hotkey::ToggleOverlay),
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => self.rewinding = true,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => self.rewinding = false,
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    if let Some(key) = self.map_keycode(keycode) {
+                        self.pressed_keys[key as usize] = true;
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(key) = self.map_keycode(keycode) {
+                        self.pressed_keys[key as usize] = false;
+                    }
+                },
+                // the KeyUp events are lost while the window is unfocused
+                Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                    self.pressed_keys = [false; 16];
+                    self.rewinding = false;
+                },
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => self.hotkeys.push(Hotkey::CloseWindow(window_id)),
+                Event::Quit {..} => return Ok(false),
+                _ => continue,
+            };
+        }
+
+        Ok(true)
+    }
+
+    fn keys(&self) -> [bool; 16] {
+        self.pressed_keys
+    }
+}
+
+/// Converts a keymap of SDL key names, as read by cli::parse_keymap, into keycodes
+pub fn keymap_from_names(names: &[(String, u8)]) -> Result<Vec<(Keycode, u8)>, String> {
+    names.iter().map(|(name, key)| {
+        Keycode::from_name(name).map(|keycode| (keycode, *key)).ok_or_else(|| format!("unknown key name '{}' in keymap", name))
+    }).collect()
+}
+
+/// The 1234/QWER/ASDF/ZXCV block of a QWERTY keyboard mapped onto the hex keypad
+pub fn default_keymap() -> Vec<(Keycode, u8)> {
+    vec![
+        (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+        (Keycode::Q, 0x4), (Keycode::W, 0x5), (Keycode::E, 0x6), (Keycode::R, 0xD),
+        (Keycode::A, 0x7), (Keycode::S, 0x8), (Keycode::D, 0x9), (Keycode::F, 0xE),
+        (Keycode::Z, 0xA), (Keycode::X, 0x0), (Keycode::C, 0xB), (Keycode::V, 0xF),
+    ]
+}
@@ -0,0 +1,141 @@
+use crate::instruction;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// How much the emulator reports while running, each level includes the ones before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Off,
+    Error,
+    Info,
+    Debug,
+    /// one line per executed instruction
+    Trace,
+}
+
+impl FromStr for TraceLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<TraceLevel, String> {
+        match level {
+            "off" => Ok(TraceLevel::Off),
+            "error" => Ok(TraceLevel::Error),
+            "info" => Ok(TraceLevel::Info),
+            "debug" => Ok(TraceLevel::Debug),
+            "trace" => Ok(TraceLevel::Trace),
+            _ => Err(format!("unknown trace level '{}', expected off, error, info, debug or trace", level)),
+        }
+    }
+}
+
+/// Which executed instructions are traced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceFilter {
+    // bit N set : the opcodes whose first nibble is N are traced
+    pub classes: u16,
+    // first and last traced addresses, inclusive
+    pub pc_range: (usize, usize),
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter {
+            classes: 0xFFFF,
+            pc_range: (0, usize::MAX),
+        }
+    }
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: usize, opcode: u16) -> bool {
+        (self.classes >> (opcode >> 12)) & 0x1 == 1 && pc >= self.pc_range.0 && pc <= self.pc_range.1
+    }
+
+    /// Parses the opcode classes to trace, first nibbles separated by commas like `8,D,F`
+    pub fn parse_classes(text: &str) -> Result<u16, String> {
+        text.split(',').try_fold(0u16, |classes, class| match u8::from_str_radix(class.trim(), 16) {
+            Ok(class) if class < 16 => Ok(classes | 1 << class),
+            _ => Err(format!("invalid opcode class '{}', expected a hex digit", class.trim())),
+        })
+    }
+
+    /// Parses a range of addresses in hex like `200-2FF`, or a single address
+    pub fn parse_pc_range(text: &str) -> Result<(usize, usize), String> {
+        let addr = |text: &str| usize::from_str_radix(text.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid address '{}' in the pc range", text.trim()));
+        let (first, last) = match text.split_once('-') {
+            Some((first, last)) => (addr(first)?, addr(last)?),
+            None => (addr(text)?, addr(text)?),
+        };
+        if first > last {
+            return Err(format!("the pc range {} ends before it starts", text));
+        }
+
+        Ok((first, last))
+    }
+}
+
+/// State of the machine right before an instruction runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub pc: usize,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: usize,
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl fmt::Display for TraceRecord {
+    /// One line with fixed width fields, meant to be diffed against the traces of other emulators
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PC={:04X} OP={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X} V=", self.pc, self.opcode, self.i, self.sp, self.delay_timer, self.sound_timer)?;
+        self.v.iter().try_for_each(|v| write!(f, "{:02X}", v))
+    }
+}
+
+/// Writes a TraceRecord line for every executed instruction the filter lets through
+pub struct Tracer {
+    filter: TraceFilter,
+    out: Box<dyn Write>,
+    // adds the mnemonic after each line, for reading rather than diffing
+    annotate: bool,
+}
+
+impl Tracer {
+    pub fn new(filter: TraceFilter, out: Box<dyn Write>, annotate: bool) -> Tracer {
+        Tracer {
+            filter,
+            out,
+            annotate,
+        }
+    }
+
+    pub fn matches(&self, pc: usize, opcode: u16) -> bool {
+        self.filter.matches(pc, opcode)
+    }
+
+    /// `bytes` are the bytes at pc, only used to print the mnemonic
+    pub fn write(&mut self, record: &TraceRecord, bytes: &[u8]) -> io::Result<()> {
+        if !self.annotate {
+            return writeln!(self.out, "{}", record);
+        }
+
+        match instruction::decode(bytes) {
+            Some(instruction) => writeln!(self.out, "{}  ; {}", record, instruction),
+            None => writeln!(self.out, "{}  ; ???", record),
+        }
+    }
+
+    /// Writes out what is still buffered, the output may never be dropped when the process exits
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+#[path = "trace_test.rs"]
+mod trace_test;
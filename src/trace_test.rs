@@ -0,0 +1,56 @@
+use super::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// keeps what the tracer wrote readable after the tracer took ownership of it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn record() -> TraceRecord {
+    let mut v = [0u8; 16];
+    v[0] = 0x12;
+    v[15] = 0x01;
+    TraceRecord { pc: 0x200, opcode: 0x6A02, v, i: 0x2EA, sp: 1, delay_timer: 0x3C, sound_timer: 0 }
+}
+
+#[test]
+fn filter_test() {
+    assert!(TraceFilter::default().matches(0xFFF, 0xF065));
+
+    let filter = TraceFilter { classes: TraceFilter::parse_classes("8, d").unwrap(), pc_range: TraceFilter::parse_pc_range("200-2FF").unwrap() };
+    assert!(filter.matches(0x200, 0x8124));
+    assert!(filter.matches(0x2FF, 0xD015));
+    assert!(!filter.matches(0x300, 0xD015));
+    assert!(!filter.matches(0x250, 0x6A02));
+
+    assert_eq!(Ok((0x300, 0x300)), TraceFilter::parse_pc_range("0x300"));
+    assert_eq!("invalid opcode class '10', expected a hex digit", TraceFilter::parse_classes("8,10").unwrap_err());
+    assert_eq!("invalid address 'x' in the pc range", TraceFilter::parse_pc_range("200-x").unwrap_err());
+    assert_eq!("the pc range 300-200 ends before it starts", TraceFilter::parse_pc_range("300-200").unwrap_err());
+}
+
+#[test]
+fn tracer_test() {
+    assert_eq!("PC=0200 OP=6A02 I=02EA SP=1 DT=3C ST=00 V=12000000000000000000000000000001", record().to_string());
+
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(TraceFilter::default(), Box::new(buffer.clone()), true);
+    tracer.write(&record(), &[0x6A, 0x02]).unwrap();
+    tracer.write(&TraceRecord { opcode: 0xF000, ..record() }, &[0xF0, 0x00, 0x03, 0x00]).unwrap();
+    tracer.write(&TraceRecord { opcode: 0xFFFF, ..record() }, &[0xFF, 0xFF]).unwrap();
+
+    let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let annotations: Vec<&str> = text.lines().map(|line| line.split("  ; ").nth(1).unwrap()).collect();
+    assert_eq!(vec!["LD VA, 0x02", "LD I, LONG 0x0300", "???"], annotations);
+}
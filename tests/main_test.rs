@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn trace_file_test() {
+    let path = std::env::temp_dir().join("chip8_trace_file_test.trace");
+    let status = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .args(["--headless", "--frames", "30", "--ips", "600", "--trace", "off", "--trace-file"])
+        .arg(&path)
+        .arg("games/PONG")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // 10 instructions per frame, every record complete
+    let trace = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(300, trace.lines().count());
+    assert!(trace.ends_with('\n'));
+    assert!(trace.lines().all(|line| line.starts_with("PC=") && line.split("V=").nth(1).is_some_and(|v| v.len() == 32)));
+}
+
+#[test]
+fn trace_file_error_test() {
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .args(["--headless", "--frames", "1", "--trace-file", "no/such/dir/chip8.trace", "games/PONG"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Could not create the trace file no/such/dir/chip8.trace : "));
+    assert!(!stderr.contains("Could not load game!"));
+}
+
+#[test]
+fn profile_file_test() {
+    let path = std::env::temp_dir().join("chip8_profile_file_test.prof");
+    let status = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .args(["--headless", "--frames", "30", "--ips", "600", "--trace", "off", "--profile"])
+        .arg(&path)
+        .arg("games/PONG")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // the report, a blank line then the annotated listing
+    let profile = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(profile.starts_with("300 instructions executed\n"));
+    let (report, listing) = profile.rsplit_once("\n\n").unwrap();
+    assert!(report.contains("\nSubroutines\n"));
+    assert!(listing.lines().next().is_some_and(|line| line.ends_with("; 200  6A 02")));
+}
+
+#[test]
+fn rom_from_stdin_test() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .args(["--headless", "--frames", "1", "--trace", "off", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&fs::read("games/PONG").unwrap()).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8")).args(["--headless", "games/NOT_A_GAME"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not load game!"));
+}